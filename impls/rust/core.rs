@@ -0,0 +1,544 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::fs;
+use std::cmp::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rustyline::Editor;
+
+use crate::types::{MalVal, MalArgs, MalRet, MalErr, MapKey, error, func, hash_map};
+use crate::types::MalVal::{List, Vector, Hash, Nil, Str, Sym, Keyword, Bool, Int, Big, Ratio, Float, Func, MalFunc, Atom, Pos};
+use crate::types::MalErr::{ErrString, ErrMalVal};
+use crate::env::Env;
+use crate::reader;
+use crate::number;
+
+// arithmetic builtins
+
+fn op(args: MalArgs, f: fn(&MalVal, &MalVal) -> MalRet) -> MalRet {
+    if args.len() < 2 {
+        return Err(ErrString(format!("Insufficient arguments: {} ", args.len())));
+    }
+    let mut acc = args[0].clone();
+    for mv in args[1..].iter() {
+        acc = f(&acc, mv)?;
+    }
+    Ok(acc)
+}
+
+fn add(args: MalArgs) -> MalRet { op(args, number::add) }
+fn sub(args: MalArgs) -> MalRet { op(args, number::sub) }
+fn mul(args: MalArgs) -> MalRet { op(args, number::mul) }
+fn div(args: MalArgs) -> MalRet { op(args, number::div) }
+
+fn numerator(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(n) => number::numerator(n),
+        None => error("numerator: expected a number")
+    }
+}
+
+fn denominator(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(n) => number::denominator(n),
+        None => error("denominator: expected a number")
+    }
+}
+
+// comparison builtins
+
+// NaN is unordered, so every comparison with it is false
+fn cmp(args: MalArgs, f: fn(Ordering) -> bool) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(a), Some(b)) => Ok(Bool(number::compare(a, b)?.is_some_and(f))),
+        _ => Err(ErrString(format!("Insufficient arguments: {} ", args.len())))
+    }
+}
+
+fn equal(args: MalArgs) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(a), Some(b)) => Ok(Bool(a == b)),
+        _ => Err(ErrString(format!("Insufficient arguments: {} ", args.len())))
+    }
+}
+
+// list builtins
+
+fn is_list(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(List(..)) => Ok(Bool(true)),
+        _ => Ok(Bool(false))
+    }
+}
+
+fn is_empty(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(List(v, _)) | Some(Vector(v, _)) => Ok(Bool(v.is_empty())),
+        Some(Hash(hm, _)) => Ok(Bool(hm.is_empty())),
+        Some(Nil) => Ok(Bool(true)),
+        _ => error("empty?: expected a sequence")
+    }
+}
+
+fn count(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(List(v, _)) | Some(Vector(v, _)) => Ok(Int(v.len() as i64)),
+        Some(Hash(hm, _)) => Ok(Int(hm.len() as i64)),
+        Some(Nil) => Ok(Int(0)),
+        _ => error("count: expected a sequence")
+    }
+}
+
+// (conj coll x1 xN) adds each x where it's cheapest:
+// at the front of lists, at the back of vectors
+fn conj(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(List(v, _)) => {
+            let mut res: Vec<MalVal> = args[1..].iter().rev().cloned().collect();
+            res.extend_from_slice(v);
+            Ok(list!(res))
+        },
+        Some(Vector(v, _)) => {
+            let mut res = v.to_vec();
+            res.extend_from_slice(&args[1..]);
+            Ok(vector!(res))
+        },
+        _ => error("conj: expected a list or a vector")
+    }
+}
+
+// (seq coll) turns lists, vectors and strings into a list, or nil when empty
+fn seq(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(List(v, _)) | Some(Vector(v, _)) if !v.is_empty() => Ok(list!(v.to_vec())),
+        Some(Str(s)) if !s.is_empty() => Ok(list!(s.chars().map(|c| Str(c.to_string())).collect())),
+        Some(List(..)) | Some(Vector(..)) | Some(Str(_)) | Some(Nil) => Ok(Nil),
+        _ => error("seq: expected a sequence or a string")
+    }
+}
+
+// (cons x seq) prepends x to seq, always returning a list
+fn cons(args: MalArgs) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(mv), Some(List(v, _))) | (Some(mv), Some(Vector(v, _))) => {
+            let mut res = vec![mv.clone()];
+            res.extend_from_slice(v);
+            Ok(list!(res))
+        },
+        (Some(mv), Some(Nil)) => Ok(list!(vec![mv.clone()])),
+        _ => error("cons: expected a value and a sequence")
+    }
+}
+
+// (concat seq1 seqN) joins every seq into a single list
+fn concat(args: MalArgs) -> MalRet {
+    let mut res = vec![];
+    for seq in args.iter() {
+        match seq {
+            List(v, _) | Vector(v, _) => res.extend_from_slice(v),
+            Nil => (),
+            _ => return error("concat: expected sequences")
+        }
+    }
+    Ok(list!(res))
+}
+
+fn vec(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(List(v, _)) | Some(Vector(v, _)) => Ok(vector!(v.to_vec())),
+        Some(Nil) => Ok(vector![]),
+        _ => error("vec: expected a sequence")
+    }
+}
+
+fn nth(args: MalArgs) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(List(v, _)), Some(Int(i))) | (Some(Vector(v, _)), Some(Int(i))) => {
+            match v.get(*i as usize) {
+                Some(mv) if *i >= 0 => Ok(mv.clone()),
+                _ => error(&format!("nth: index {} out of range", i))
+            }
+        },
+        _ => error("nth: expected a sequence and an index")
+    }
+}
+
+fn first(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(List(v, _)) | Some(Vector(v, _)) => Ok(v.first().unwrap_or(&Nil).clone()),
+        Some(Nil) => Ok(Nil),
+        _ => error("first: expected a sequence")
+    }
+}
+
+fn rest(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(List(v, _)) | Some(Vector(v, _)) => Ok(list!(v.iter().skip(1).cloned().collect())),
+        Some(Nil) => Ok(list![]),
+        _ => error("rest: expected a sequence")
+    }
+}
+
+// string builtins
+
+fn join(args: &[MalVal], sep: &str, print_readably: bool) -> Result<String, MalErr> {
+    Ok(args.iter()
+        .map(|mv| mv.try_pr_str(print_readably))
+        .collect::<Result<Vec<String>, MalErr>>()?
+        .join(sep))
+}
+
+fn pr_str(args: MalArgs) -> MalRet {
+    Ok(Str(join(&args, " ", true)?))
+}
+
+fn concat_str(args: MalArgs) -> MalRet {
+    Ok(Str(join(&args, "", false)?))
+}
+
+fn prn(args: MalArgs) -> MalRet {
+    println!("{}", join(&args, " ", true)?);
+    Ok(Nil)
+}
+
+fn print_line(args: MalArgs) -> MalRet {
+    println!("{}", join(&args, " ", false)?);
+    Ok(Nil)
+}
+
+// (read-string s file?) names file in reader errors and form positions
+fn read_string(args: MalArgs) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(Str(s)), None) => reader::read_str(s.to_string()),
+        (Some(Str(s)), Some(Str(file))) => reader::read_str_from(s.to_string(), Some(file.to_string())),
+        _ => error("read-string: expected a string and an optional file name")
+    }
+}
+
+// (read-all s file?) is like read-string, but reads every form in s into a list
+fn read_all(args: MalArgs) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(Str(s)), None) => Ok(list!(reader::read_all(s.to_string())?)),
+        (Some(Str(s)), Some(Str(file))) => Ok(list!(reader::read_all_from(s.to_string(), Some(file.to_string()))?)),
+        _ => error("read-all: expected a string and an optional file name")
+    }
+}
+
+fn slurp(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(Str(path)) => match fs::read_to_string(path) {
+            Ok(s) => Ok(Str(s)),
+            Err(e) => error(&format!("slurp: cannot read '{}': {}", path, e))
+        },
+        _ => error("slurp: expected a file name")
+    }
+}
+
+fn readline(args: MalArgs) -> MalRet {
+    thread_local! {
+        static RL: RefCell<Editor<()>> = RefCell::new(Editor::<()>::new());
+    }
+    match args.first() {
+        Some(Str(prompt)) => RL.with(|rl| match rl.borrow_mut().readline(prompt) {
+            // without a terminal the line is read as is, newline included
+            Ok(line) => Ok(Str(line.trim_end_matches('\n').to_string())),
+            Err(_) => Ok(Nil)
+        }),
+        _ => error("readline: expected a prompt")
+    }
+}
+
+fn time_ms(_args: MalArgs) -> MalRet {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => Ok(Int(d.as_millis() as i64)),
+        Err(e) => error(&format!("time-ms: {}", e))
+    }
+}
+
+// metadata builtins
+
+// a list read from a file gets {:file f :line l :column c}, like clojure's reader
+fn meta(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(List(_, m)) => match &**m {
+            Pos(file, line, col) => hash_map(vec![
+                Keyword("file".to_string()), Str(file.to_string()),
+                Keyword("line".to_string()), Int(*line as i64),
+                Keyword("column".to_string()), Int(*col as i64)
+            ]),
+            m => Ok(m.clone())
+        },
+        Some(Vector(_, m)) | Some(Hash(_, m)) | Some(Func(_, m)) | Some(MalFunc{meta: m, ..}) => Ok((**m).clone()),
+        Some(_) => Ok(Nil),
+        None => error("meta: expected a value")
+    }
+}
+
+fn with_meta(args: MalArgs) -> MalRet {
+    let m = Rc::new(args.get(1).cloned().unwrap_or(Nil));
+    match args.first() {
+        Some(List(v, _)) => Ok(List(v.clone(), m)),
+        Some(Vector(v, _)) => Ok(Vector(v.clone(), m)),
+        Some(Hash(hm, _)) => Ok(Hash(hm.clone(), m)),
+        Some(Func(f, _)) => Ok(Func(*f, m)),
+        Some(MalFunc{eval, params, ast, env, is_macro, ..}) => Ok(MalFunc{
+            eval: *eval,
+            params: params.clone(),
+            ast: ast.clone(),
+            env: env.clone(),
+            is_macro: *is_macro,
+            meta: m,
+        }),
+        _ => error("with-meta: expected a collection or a function")
+    }
+}
+
+// atom builtins
+
+fn atom(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(mv) => Ok(Atom(Rc::new(RefCell::new(mv.clone())))),
+        None => error("atom: expected a value")
+    }
+}
+
+fn is_atom(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(Atom(_)) => Ok(Bool(true)),
+        _ => Ok(Bool(false))
+    }
+}
+
+fn deref(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(Atom(a)) => Ok(a.borrow().clone()),
+        _ => error("deref: expected an atom")
+    }
+}
+
+fn reset(args: MalArgs) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(Atom(a)), Some(mv)) => {
+            *a.borrow_mut() = mv.clone();
+            Ok(mv.clone())
+        },
+        _ => error("reset!: expected an atom and a value")
+    }
+}
+
+// (swap! atom f arg1 argN) sets the atom to (f @atom arg1 argN)
+fn swap(args: MalArgs) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(Atom(a)), Some(f)) => {
+            let mut fargs = vec![a.borrow().clone()];
+            fargs.extend_from_slice(&args[2..]);
+            // the borrow is released before calling f, which may deref the atom
+            let res = f.apply(fargs)?;
+            *a.borrow_mut() = res.clone();
+            Ok(res)
+        },
+        _ => error("swap!: expected an atom and a function")
+    }
+}
+
+// type builtins
+
+fn is(args: MalArgs, pred: fn(&MalVal) -> bool) -> MalRet {
+    match args.first() {
+        Some(mv) => Ok(Bool(pred(mv))),
+        None => error("expected a value")
+    }
+}
+
+fn symbol(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(Str(s)) => Ok(Sym(s.to_string())),
+        _ => error("symbol: expected a string")
+    }
+}
+
+fn keyword(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(Str(s)) => Ok(Keyword(s.to_string())),
+        Some(kw @ Keyword(_)) => Ok(kw.clone()),
+        _ => error("keyword: expected a string")
+    }
+}
+
+// hash-map builtins
+
+fn assoc(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(Hash(hm, _)) => {
+            let kvs = hash_map(args[1..].to_vec())?;
+            match &kvs {
+                Hash(new, _) => {
+                    let mut res = (**hm).clone();
+                    res.extend(new.iter().map(|(k, v)| (k.clone(), v.clone())));
+                    Ok(Hash(Rc::new(res), Rc::new(Nil)))
+                },
+                _ => error("assoc: expected keys and values")
+            }
+        },
+        _ => error("assoc: expected a hash-map")
+    }
+}
+
+fn dissoc(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(Hash(hm, _)) => {
+            let mut res = (**hm).clone();
+            for k in args[1..].iter() {
+                res.remove(&MapKey::from_mal_val(k)?);
+            }
+            Ok(Hash(Rc::new(res), Rc::new(Nil)))
+        },
+        _ => error("dissoc: expected a hash-map")
+    }
+}
+
+fn get(args: MalArgs) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(Hash(hm, _)), Some(k)) => Ok(hm.get(&MapKey::from_mal_val(k)?).unwrap_or(&Nil).clone()),
+        (Some(Nil), Some(_)) => Ok(Nil),
+        _ => error("get: expected a hash-map and a key")
+    }
+}
+
+fn contains(args: MalArgs) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(Hash(hm, _)), Some(k)) => Ok(Bool(hm.contains_key(&MapKey::from_mal_val(k)?))),
+        _ => error("contains?: expected a hash-map and a key")
+    }
+}
+
+fn keys(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(Hash(hm, _)) => Ok(list!(hm.keys().map(|k| k.mal_val()).collect())),
+        _ => error("keys: expected a hash-map")
+    }
+}
+
+fn vals(args: MalArgs) -> MalRet {
+    match args.first() {
+        Some(Hash(hm, _)) => Ok(list!(hm.values().cloned().collect())),
+        _ => error("vals: expected a hash-map")
+    }
+}
+
+// function builtins
+
+fn throw(args: MalArgs) -> MalRet {
+    Err(ErrMalVal(args.first().unwrap_or(&Nil).clone()))
+}
+
+// (apply f arg1 argN seq) calls f with arg1 argN followed by the items of seq
+fn apply(args: MalArgs) -> MalRet {
+    match (args.first(), args.last()) {
+        (Some(f), Some(List(v, _))) | (Some(f), Some(Vector(v, _))) if args.len() > 1 => {
+            let mut fargs = args[1..args.len() - 1].to_vec();
+            fargs.extend_from_slice(v);
+            f.apply(fargs)
+        },
+        _ => error("apply: expected a function and a sequence")
+    }
+}
+
+fn map(args: MalArgs) -> MalRet {
+    match (args.first(), args.get(1)) {
+        (Some(f), Some(List(v, _))) | (Some(f), Some(Vector(v, _))) => {
+            let mut res = vec![];
+            for mv in v.iter() {
+                res.push(f.apply(vec![mv.clone()])?);
+            }
+            Ok(list!(res))
+        },
+        _ => error("map: expected a function and a sequence")
+    }
+}
+
+// the core namespace: every builtin, by the name it's bound to
+pub fn ns() -> Vec<(&'static str, MalVal)> {
+    vec![
+        ("+", func(add)),
+        ("-", func(sub)),
+        ("*", func(mul)),
+        ("/", func(div)),
+        ("numerator", func(numerator)),
+        ("denominator", func(denominator)),
+
+        ("=", func(equal)),
+        ("<", func(|args| cmp(args, |o| o == Ordering::Less))),
+        ("<=", func(|args| cmp(args, |o| o != Ordering::Greater))),
+        (">", func(|args| cmp(args, |o| o == Ordering::Greater))),
+        (">=", func(|args| cmp(args, |o| o != Ordering::Less))),
+
+        ("list", func(|args| Ok(list!(args)))),
+        ("list?", func(is_list)),
+        ("vector", func(|args| Ok(vector!(args)))),
+        ("vector?", func(|args| is(args, |mv| matches!(mv, Vector(..))))),
+        ("sequential?", func(|args| is(args, |mv| matches!(mv, List(..) | Vector(..))))),
+        ("empty?", func(is_empty)),
+        ("count", func(count)),
+        ("cons", func(cons)),
+        ("concat", func(concat)),
+        ("vec", func(vec)),
+        ("nth", func(nth)),
+        ("first", func(first)),
+        ("rest", func(rest)),
+
+        ("pr-str", func(pr_str)),
+        ("str", func(concat_str)),
+        ("prn", func(prn)),
+        ("println", func(print_line)),
+        ("read-string", func(read_string)),
+        ("read-all", func(read_all)),
+        ("slurp", func(slurp)),
+
+        ("nil?", func(|args| is(args, |mv| matches!(mv, Nil)))),
+        ("true?", func(|args| is(args, |mv| matches!(mv, Bool(true))))),
+        ("false?", func(|args| is(args, |mv| matches!(mv, Bool(false))))),
+        ("symbol?", func(|args| is(args, |mv| matches!(mv, Sym(_))))),
+        ("symbol", func(symbol)),
+        ("keyword?", func(|args| is(args, |mv| matches!(mv, Keyword(_))))),
+        ("keyword", func(keyword)),
+
+        ("hash-map", func(hash_map)),
+        ("map?", func(|args| is(args, |mv| matches!(mv, Hash(..))))),
+        ("assoc", func(assoc)),
+        ("dissoc", func(dissoc)),
+        ("get", func(get)),
+        ("contains?", func(contains)),
+        ("keys", func(keys)),
+        ("vals", func(vals)),
+
+        ("throw", func(throw)),
+        ("apply", func(apply)),
+        ("map", func(map)),
+
+        ("string?", func(|args| is(args, |mv| matches!(mv, Str(_))))),
+        ("number?", func(|args| is(args, |mv| matches!(mv, Int(_) | Big(_) | Ratio(_) | Float(_))))),
+        ("ratio?", func(|args| is(args, |mv| matches!(mv, Ratio(_))))),
+        ("fn?", func(|args| is(args, |mv| matches!(mv, Func(..) | MalFunc{is_macro: false, ..})))),
+        ("macro?", func(|args| is(args, |mv| matches!(mv, MalFunc{is_macro: true, ..})))),
+        ("conj", func(conj)),
+        ("seq", func(seq)),
+        ("readline", func(readline)),
+        ("time-ms", func(time_ms)),
+        ("meta", func(meta)),
+        ("with-meta", func(with_meta)),
+
+        ("atom", func(atom)),
+        ("atom?", func(is_atom)),
+        ("deref", func(deref)),
+        ("reset!", func(reset)),
+        ("swap!", func(swap)),
+    ]
+}
+
+// binds the whole core namespace into `env`
+pub fn install(env: &Env) {
+    for (name, f) in ns() {
+        let _ = env.set(name.to_string(), f);
+    }
+}
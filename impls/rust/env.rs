@@ -1,51 +1,102 @@
 use std::rc::Rc;
 use fnv::FnvHashMap;
 use std::fmt;
+use std::cell::RefCell;
 
 use crate::types::{MalVal, MalArgs, MalRet, MalErr, MapKey};
 use crate::types::MapKey::{Ks, Kw};
 use crate::types::MalVal::{List, Vector, Hash, Nil, Str, Sym, Bool, Int, Atom, Keyword, Func};
 use crate::types::MalErr::ErrString;
 
-#[derive(Debug, Clone)]
-pub struct Env {
+// an env is a shared handle: cloning it is O(1) and every clone sees
+// the same bindings, so closures see definitions made after their creation
+#[derive(Clone)]
+pub struct Env(Rc<RefCell<Scope>>);
+
+struct Scope {
     data: FnvHashMap<String, MalVal>,
-    outer: Option<Box<Env>>
+    outer: Option<Env>
 }
 
 pub fn make_env(outer: Option<Env>) -> Env {
-    Env {
+    Env(Rc::new(RefCell::new(Scope {
         data: FnvHashMap::default(),
-        outer: match outer {
-            Some(o) => Some(Box::new(o)),
-            None    => None
-        },
-    }
+        outer,
+    })))
 }
 
+// envs are cyclic (a closure captures the env it's bound in), so only
+// the local names are printed
+impl fmt::Debug for Env {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scope = self.0.borrow();
+        let mut keys: Vec<&String> = scope.data.keys().collect();
+        keys.sort();
+        write!(f, "Env {:?}", keys)
+    }
+}
 
 impl Env {
 
-    pub fn set(&mut self, sym: String, val: MalVal) -> MalRet {
-        // Why dup?
-        let dup = val.clone();
-        self.data.insert(sym, val);
-        Ok(dup)
+    pub fn set(&self, sym: String, val: MalVal) -> MalRet {
+        self.0.borrow_mut().data.insert(sym, val.clone());
+        Ok(val)
     }
 
     pub fn find(&self, sym: String) -> MalRet {
-        let mk = sym.to_string();
-        match self.data.get(&mk) {
-            Some(mr) => Ok(mr.clone()), // TODO why clone?
-            None => match &self.outer {
+        let scope = self.0.borrow();
+        match scope.data.get(&sym) {
+            Some(mr) => Ok(mr.clone()),
+            None => match &scope.outer {
                 Some(o) => o.find(sym),
-                // unknow symbols evalutate to themselves, for builtins like def! and let*
-                None => Ok(Sym(sym)) 
+                // special forms like def! and let* are handled by eval before lookup
+                None => Err(ErrString(format!("'{}' not found", sym)))
             }
         }
     }
 
+    // every name bound in this env or an outer one, e.g. for completion
+    pub fn symbols(&self) -> Vec<String> {
+        let scope = self.0.borrow();
+        let mut syms: Vec<String> = scope.data.keys().cloned().collect();
+        if let Some(o) = &scope.outer {
+            syms.extend(o.symbols());
+        }
+        syms
+    }
+
     pub fn get(&self, sym: String) -> MalRet {
       self.find(sym)
   }
-}
\ No newline at end of file
+}
+
+// binds each param symbol to its argument in a fresh env on top of `outer`
+// a `&` param binds the remaining args as a list: (fn* (a & more) ...)
+pub fn env_bind(outer: Option<Env>, params: &MalVal, args: MalArgs) -> Result<Env, MalErr> {
+    let env = make_env(outer);
+    match params {
+        List(ps, _) | Vector(ps, _) => {
+            for (i, p) in ps.iter().enumerate() {
+                match p {
+                    Sym(s) if s == "&" => {
+                        match ps.get(i + 1) {
+                            Some(Sym(rest)) => {
+                                let more = args.get(i..).map(|a| a.to_vec()).unwrap_or_default();
+                                env.set(rest.to_string(), list!(more))?;
+                                return Ok(env);
+                            },
+                            _ => return Err(ErrString("fn*: expected a symbol after '&'".to_string()))
+                        }
+                    },
+                    Sym(s) => {
+                        let arg = args.get(i).ok_or_else(|| ErrString(format!("fn*: missing argument for '{}'", s)))?;
+                        env.set(s.to_string(), arg.clone())?;
+                    },
+                    _ => return Err(ErrString(format!("fn*: param is not a symbol: '{}'", p.pr_str(true))))
+                }
+            }
+            Ok(env)
+        },
+        _ => Err(ErrString("fn*: params is not a list".to_string()))
+    }
+}
@@ -0,0 +1,137 @@
+use std::cmp::Ordering;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+
+use crate::types::{MalVal, MalRet, MalErr, error, big, ratio};
+use crate::types::MalVal::{Int, Big, Ratio, Float};
+use crate::types::MalErr::ErrString;
+
+// numeric tower: int < bignum < ratio < float
+// int ops that overflow an i64 promote to bignums, and results are
+// normalized back down, so every number has a single representation:
+// bignums that fit in an i64 become ints, ratios like 4/2 become ints.
+// Mixing two numbers promotes both to the higher of the two.
+
+enum Pair {
+    Ints(i64, i64),
+    Bigs(BigInt, BigInt),
+    Ratios(BigRational, BigRational),
+    Floats(f64, f64),
+}
+
+use self::Pair::{Ints, Bigs, Ratios, Floats};
+
+fn to_big(n: &MalVal) -> Option<BigInt> {
+    match n {
+        Int(i) => Some(BigInt::from(*i)),
+        Big(b) => Some((**b).clone()),
+        _ => None
+    }
+}
+
+fn to_ratio(n: &MalVal) -> Option<BigRational> {
+    match n {
+        Ratio(r) => Some((**r).clone()),
+        _ => to_big(n).map(BigRational::from_integer)
+    }
+}
+
+fn to_float(n: &MalVal) -> Option<f64> {
+    match n {
+        Int(i) => Some(*i as f64),
+        Big(b) => Some(b.to_f64().unwrap_or(f64::NAN)),
+        Ratio(r) => Some(r.to_f64().unwrap_or(f64::NAN)),
+        Float(x) => Some(*x),
+        _ => None
+    }
+}
+
+fn promote(a: &MalVal, b: &MalVal) -> Result<Pair, MalErr> {
+    if let (Int(x), Int(y)) = (a, b) {
+        return Ok(Ints(*x, *y));
+    }
+    if let (Some(x), Some(y)) = (to_big(a), to_big(b)) {
+        return Ok(Bigs(x, y));
+    }
+    if !matches!(a, Float(_)) && !matches!(b, Float(_)) {
+        if let (Some(x), Some(y)) = (to_ratio(a), to_ratio(b)) {
+            return Ok(Ratios(x, y));
+        }
+    }
+    match (to_float(a), to_float(b)) {
+        (Some(x), Some(y)) => Ok(Floats(x, y)),
+        (None, _) => Err(ErrString(format!("'{}' is not a number", a.pr_str(true)))),
+        (_, None) => Err(ErrString(format!("'{}' is not a number", b.pr_str(true)))),
+    }
+}
+
+pub fn add(a: &MalVal, b: &MalVal) -> MalRet {
+    match promote(a, b)? {
+        Ints(x, y) => Ok(x.checked_add(y).map_or_else(|| big(BigInt::from(x) + y), Int)),
+        Bigs(x, y) => Ok(big(x + y)),
+        Ratios(x, y) => Ok(ratio(x + y)),
+        Floats(x, y) => Ok(Float(x + y)),
+    }
+}
+
+pub fn sub(a: &MalVal, b: &MalVal) -> MalRet {
+    match promote(a, b)? {
+        Ints(x, y) => Ok(x.checked_sub(y).map_or_else(|| big(BigInt::from(x) - y), Int)),
+        Bigs(x, y) => Ok(big(x - y)),
+        Ratios(x, y) => Ok(ratio(x - y)),
+        Floats(x, y) => Ok(Float(x - y)),
+    }
+}
+
+pub fn mul(a: &MalVal, b: &MalVal) -> MalRet {
+    match promote(a, b)? {
+        Ints(x, y) => Ok(x.checked_mul(y).map_or_else(|| big(BigInt::from(x) * y), Int)),
+        Bigs(x, y) => Ok(big(x * y)),
+        Ratios(x, y) => Ok(ratio(x * y)),
+        Floats(x, y) => Ok(Float(x * y)),
+    }
+}
+
+// exact: int division that doesn't divide evenly gives a ratio
+// int division by zero is a mal error, float division follows IEEE 754
+pub fn div(a: &MalVal, b: &MalVal) -> MalRet {
+    match promote(a, b)? {
+        Ints(_, 0) => error("divide by zero"),
+        Ints(x, y) if x.checked_rem(y) == Some(0) => Ok(Int(x / y)),
+        Ints(x, y) => Ok(ratio(BigRational::new(BigInt::from(x), BigInt::from(y)))),
+        Bigs(_, y) if y.is_zero() => error("divide by zero"),
+        Bigs(x, y) => Ok(ratio(BigRational::new(x, y))),
+        Ratios(_, y) if y.is_zero() => error("divide by zero"),
+        Ratios(x, y) => Ok(ratio(x / y)),
+        Floats(x, y) => Ok(Float(x / y)),
+    }
+}
+
+// None when the numbers can't be ordered, i.e. one of them is NaN
+pub fn compare(a: &MalVal, b: &MalVal) -> Result<Option<Ordering>, MalErr> {
+    match promote(a, b)? {
+        Ints(x, y) => Ok(Some(x.cmp(&y))),
+        Bigs(x, y) => Ok(Some(x.cmp(&y))),
+        Ratios(x, y) => Ok(Some(x.cmp(&y))),
+        Floats(x, y) => Ok(x.partial_cmp(&y)),
+    }
+}
+
+// the numerator and denominator of a ratio; an int is its own numerator over 1
+pub fn numerator(n: &MalVal) -> MalRet {
+    match n {
+        Int(_) | Big(_) => Ok(n.clone()),
+        Ratio(r) => Ok(big(r.numer().clone())),
+        _ => Err(ErrString(format!("'{}' is not a rational", n.pr_str(true)))),
+    }
+}
+
+pub fn denominator(n: &MalVal) -> MalRet {
+    match n {
+        Int(_) | Big(_) => Ok(Int(1)),
+        Ratio(r) => Ok(big(r.denom().clone())),
+        _ => Err(ErrString(format!("'{}' is not a rational", n.pr_str(true)))),
+    }
+}
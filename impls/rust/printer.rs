@@ -1,41 +1,88 @@
 use std::rc::Rc;
+use std::cell::RefCell;
 use fnv::FnvHashMap;
 
-use crate::types::{MalVal};
-use crate::types::MalVal::{List, Vector, Hash, Nil, Str, Sym, Bool, Int, Atom, Keyword, Func};
+use crate::types::{MalVal, MalErr, Nesting};
+use crate::types::MalVal::{List, Vector, Hash, Nil, Str, Sym, Bool, Int, Big, Ratio, Float, Atom, Keyword, Func, MalFunc, Pos};
 
 
 impl MalVal {
 
-    pub fn pr_str(&self) -> String {
+    // print_readably escapes strings so the output can be read back,
+    // otherwise strings are printed as they are; values nested too deep to
+    // print, which only messages should come across, are shown as ...
+    pub fn pr_str(&self, print_readably: bool) -> String {
+        self.try_pr_str(print_readably).unwrap_or_else(|_| String::from("..."))
+    }
 
-        match self {
+    // pr_str, or an error for a value nested deeper than types::MAX_NESTING
+    pub fn try_pr_str(&self, print_readably: bool) -> Result<String, MalErr> {
+        let _nesting = Nesting::enter("print")?;
+        Ok(match self {
             Nil => String::from("nil"),
-            Str(s) => String::from(format!("\"{}\"", s)),
+            Str(s) if print_readably => format!("\"{}\"", escape(s)),
+            Str(s) => s.to_string(),
             Sym(s) => String::from(s),
-            Keyword(s) => String::from(format!(":{}", s)),
-            Bool(b) => String::from(b.to_string()),
-            Int(i) => String::from(i.to_string()),
-            List(mvs, _) => format!("({})", print_seq(&**mvs)),
-            Vector(mvs, _) => format!("[{}]", print_seq(&**mvs)),
+            Keyword(s) => format!(":{}", s),
+            Bool(b) => b.to_string(),
+            Int(i) => i.to_string(),
+            Big(i) => i.to_string(),
+            Ratio(r) => r.to_string(),
+            Float(x) => print_float(*x),
+            List(mvs, _) => format!("({})", print_seq(mvs, print_readably)?),
+            Vector(mvs, _) => format!("[{}]", print_seq(mvs, print_readably)?),
             Hash(mvs, _) => {
                 let kvs: Vec<MalVal> = mvs
                                         .iter()
                                         .flat_map(|(k, v)| { vec![k.mal_val(), v.clone()] } )
                                         .collect();
-                format!("{{{}}}", print_seq(&kvs))
+                format!("{{{}}}", print_seq(&kvs, print_readably)?)
+            },
+            Func(f, _) => format!("<func {:?}", f),
+            MalFunc{..} => String::from("#<function>"),
+            Atom(a) => {
+                // an atom can hold itself, directly or inside a collection,
+                // so an atom already being printed isn't printed again
+                let ptr = Rc::as_ptr(a);
+                if PRINTING_ATOMS.with(|p| p.borrow().contains(&ptr)) {
+                    return Ok(String::from("(atom ...)"));
+                }
+                PRINTING_ATOMS.with(|p| p.borrow_mut().push(ptr));
+                let res = a.borrow().try_pr_str(print_readably);
+                PRINTING_ATOMS.with(|p| p.borrow_mut().pop());
+                format!("(atom {})", res?)
             },
-            Func(f) => format!("<func {:?}", f),
-            Atom() => String::from("@")
-        }
+            Pos(file, line, col) => format!("#<{}:{}:{}>", file, line, col)
+        })
     }
 }
 
-pub fn print_seq(v: &Vec<MalVal>) -> String {
+thread_local! {
+    // the atoms being printed, innermost last
+    static PRINTING_ATOMS: RefCell<Vec<*const RefCell<MalVal>>> = const { RefCell::new(Vec::new()) };
+}
+
+// floats always show a decimal point or an exponent, so they read back as floats
+fn print_float(x: f64) -> String {
+    if x.is_nan() {
+        String::from("##NaN")
+    } else if x.is_infinite() {
+        String::from(if x > 0.0 { "##Inf" } else { "##-Inf" })
+    } else {
+        format!("{:?}", x)
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+     .replace('"', "\\\"")
+     .replace('\n', "\\n")
+}
+
+pub fn print_seq(v: &[MalVal], print_readably: bool) -> Result<String, MalErr> {
     // println!("SEQ {:?}", v);
-    v.iter()
-     .map(|mv| mv.pr_str())
-     .collect::<Vec<String>>()
-     .join(" ")
-    
+    Ok(v.iter()
+     .map(|mv| mv.try_pr_str(print_readably))
+     .collect::<Result<Vec<String>, MalErr>>()?
+     .join(" "))
 }
\ No newline at end of file
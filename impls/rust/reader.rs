@@ -1,138 +1,391 @@
-use lazy_static::lazy_static;
-use regex::{Captures, Regex};
 use std::rc::Rc;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::Zero;
 
 use crate::types::MalErr::ErrString;
-use crate::types::{MalErr, MalVal, MalRet, error, hash_map};
-use crate::types::MalVal::{List, Vector, Nil, Str, Int, Bool, Keyword, Sym};
+use crate::types::{MalErr, MalVal, MalRet, error, hash_map, big, ratio};
+use crate::types::MalVal::{List, Vector, Nil, Str, Int, Float, Bool, Keyword, Sym};
 
-#[derive(Debug)]
-pub struct Reader {
-    tokens: Vec<String>,
-    position: usize
+// where a token starts: byte offset into the input, 1-based line and column
+#[derive(Debug, Clone, Copy)]
+pub struct Pos {
+    pub offset: usize,
+    pub line: usize,
+    pub col: usize
 }
 
-impl Reader {
-    fn get(&self, i: usize)-> Result<String, MalErr>  {
-        return Ok(self
-            .tokens
-            .get(i)
-            .ok_or(ErrString("reader: end of input".to_string()))?
-            .to_string());
+// a slice of the input, no copy is made until an atom is built from it
+#[derive(Debug, Clone, Copy)]
+pub struct Token<'a> {
+    pub text: &'a str,
+    pub pos: Pos
+}
+
+// splits the input into tokens:
+//   ~@  [ ] { } ( ) ' ` ~ ^ @
+//   strings, possibly unterminated so the reader can complain about them
+//   atoms, i.e. anything up to whitespace, a comma or one of [ ] { } ( ' " ` ; )
+// whitespace, commas and ; comments are skipped
+pub struct Lexer<'a> {
+    src: &'a str,
+    pos: Pos
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str) -> Lexer<'a> {
+        Lexer { src, pos: Pos { offset: 0, line: 1, col: 1 } }
     }
 
-    fn next(&mut self)-> Result<String, MalErr> {
-        self.position = self.position + 1;
-        return self.get(self.position - 1);
+    // where the lexer is, i.e. the end of the input once every token is read
+    pub fn pos(&self) -> Pos {
+        self.pos
     }
-    fn peek(&mut self) -> Result<String, MalErr> {
-        return self.get(self.position);
+
+    fn peek_char(&self) -> Option<char> {
+        self.src[self.pos.offset..].chars().next()
     }
-}
 
-fn tokenize(s: &String) -> Vec<String>{
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r###"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]+)"###).unwrap();
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek_char()?;
+        self.pos.offset += c.len_utf8();
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.col = 1;
+        } else {
+            self.pos.col += 1;
+        }
+        Some(c)
     }
-    let mut res = vec![];
-    for cap in RE.captures_iter(s) {
-        // regex is line based, we just ignore comments
-        if cap[1].starts_with(";") {
-            continue;
+
+    fn bump_while(&mut self, pred: fn(char) -> bool) {
+        while let Some(c) = self.peek_char() {
+            if !pred(c) {
+                break;
+            }
+            self.bump();
         }
-        res.push(String::from(&cap[1]))
     }
-    return res;
 }
 
+fn is_blank(c: char) -> bool {
+    c.is_whitespace() || c == ','
+}
+
+pub fn is_atom_char(c: char) -> bool {
+    !is_blank(c) && !matches!(c, '[' | ']' | '{' | '}' | '(' | ')' | '\'' | '"' | '`' | ';')
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        loop {
+            self.bump_while(is_blank);
+            let start = self.pos;
+            match self.bump()? {
+                ';' => {
+                    self.bump_while(|c| c != '\n');
+                    continue;
+                },
+                '~' if self.peek_char() == Some('@') => {
+                    self.bump();
+                },
+                '[' | ']' | '{' | '}' | '(' | ')' | '\'' | '`' | '~' | '^' | '@' => {},
+                '"' => loop {
+                    match self.bump() {
+                        Some('\\') => { self.bump(); },
+                        Some('"') | None => break,
+                        _ => {}
+                    }
+                },
+                _ => self.bump_while(is_atom_char)
+            }
+            return Some(Token { text: &self.src[start.offset..self.pos.offset], pos: start });
+        }
+    }
+}
+
+// reads tokens as the lexer produces them, one token of lookahead
+pub struct Reader<'a> {
+    lexer: Lexer<'a>,
+    peeked: Option<Token<'a>>,
+    // the file being read, if any, named in errors and form positions
+    file: Option<Rc<str>>,
+    // set once reading runs into the end of the input, i.e. more input
+    // could have completed the form
+    eof: bool,
+    // how many forms are being read inside one another
+    depth: usize
+}
+
+impl<'a> Reader<'a> {
+    fn new(s: &'a str, file: Option<String>) -> Reader<'a> {
+        Reader { lexer: Lexer::new(s), peeked: None, file: file.map(Rc::from), eof: false, depth: 0 }
+    }
+
+    // true once every token has been read
+    fn at_end(&mut self) -> bool {
+        if self.peeked.is_none() {
+            self.peeked = self.lexer.next();
+        }
+        self.peeked.is_none()
+    }
+
+    fn peek(&mut self) -> Result<Token<'a>, MalErr> {
+        if self.at_end() {
+            self.eof = true;
+            // the lexer stops at the end of the input
+            return Err(self.error_at("reader: end of input", self.lexer.pos()));
+        }
+        Ok(self.peeked.unwrap())
+    }
+
+    fn next(&mut self)-> Result<Token<'a>, MalErr> {
+        let t = self.peek()?;
+        self.peeked = None;
+        Ok(t)
+    }
+
+    // file:line:col when reading a file, line and column otherwise
+    fn where_is(&self, pos: Pos) -> String {
+        match &self.file {
+            Some(f) => format!("{}:{}:{}", f, pos.line, pos.col),
+            None => format!("line {}, column {}", pos.line, pos.col)
+        }
+    }
+
+    fn error_at(&self, msg: &str, pos: Pos) -> MalErr {
+        ErrString(format!("{} at {}", msg, self.where_is(pos)))
+    }
+
+    // the metadata of a list starting at pos: where it is when reading a
+    // file, none otherwise
+    fn pos_meta(&self, pos: Pos) -> MalVal {
+        match &self.file {
+            Some(f) => MalVal::Pos(f.clone(), pos.line as u32, pos.col as u32),
+            None => Nil
+        }
+    }
+}
 
 pub fn read_str(s: String)-> MalRet {
-    let tokens = tokenize(&s);    
-    if tokens.len() == 0 {
+    read_str_from(s, None)
+}
+
+// reads s as the contents of file, so errors and form positions name it;
+// s must hold exactly one form, read_all reads more
+pub fn read_str_from(s: String, file: Option<String>)-> MalRet {
+    let mut r = Reader::new(&s, file);
+    if r.at_end() {
         return error("no input");
     }
-    read_form(&mut Reader {
-        position: 0, tokens: tokens
-    })
+    let form = read_form(&mut r)?;
+    if !r.at_end() {
+        let t = check_unmatched(&mut r)?;
+        return Err(r.error_at(&format!("reader: expected one form, got more starting with '{}'", t.text), t.pos));
+    }
+    Ok(form)
+}
+
+pub fn read_all(s: String) -> Result<Vec<MalVal>, MalErr> {
+    read_all_from(s, None)
+}
+
+// reads every form in s, in order; no forms at all is fine
+pub fn read_all_from(s: String, file: Option<String>) -> Result<Vec<MalVal>, MalErr> {
+    read_forms(&mut Reader::new(&s, file))
+}
+
+// true when s ends in the middle of a form, an unclosed list or an
+// unterminated string, so more input could complete it
+pub fn is_incomplete(s: &str) -> bool {
+    let mut r = Reader::new(s, None);
+    read_forms(&mut r).is_err() && r.eof
+}
+
+fn read_forms(r: &mut Reader) -> Result<Vec<MalVal>, MalErr> {
+    let mut forms = vec![];
+    while !r.at_end() {
+        check_unmatched(r)?;
+        forms.push(read_form(r)?);
+    }
+    Ok(forms)
+}
+
+// a closing bracket between forms has nothing to close;
+// returns the next token otherwise
+fn check_unmatched<'a>(r: &mut Reader<'a>) -> Result<Token<'a>, MalErr> {
+    let t = r.peek()?;
+    if matches!(t.text, ")" | "]" | "}") {
+        return Err(r.error_at(&format!("reader: unmatched '{}'", t.text), t.pos));
+    }
+    Ok(t)
 }
 
 fn read_list(r: &mut Reader) -> MalRet {
     let start = r.next()?;
-    let stop = match &start[..] {
+    let stop = match start.text {
         "(" => ")",
         "[" => "]",
         "{" => "}",
-        _ => panic!("reader: cannot read list starting with '{}'", start)
+        _ => return Err(r.error_at(&format!("reader: cannot read list starting with '{}'", start.text), start.pos))
     };
 
     let mut seq: Vec<MalVal> = vec![];
-    let finish: String;
     loop {
         let n = r.peek()?;
-        if n == stop {
-            finish = n;
+        if n.text == stop {
             break;
         }
         let mv = read_form(r)?;
         seq.push(mv);
     }
     let _ = r.next();
-    match &stop[..] {
-        ")" => Ok(list!(seq)),
+    match stop {
+        // lists read from a file remember where, so errors can point back to them
+        ")" => Ok(List(Rc::new(seq), Rc::new(r.pos_meta(start.pos)))),
         "]" => Ok(vector!(seq)),
         "}" => {
             if seq.len() % 2 != 0 {
-                error("reader: hashmap is unbalanced")
+                Err(r.error_at("reader: hashmap is unbalanced", start.pos))
             } else {
-                hash_map(seq)
+                hash_map(seq).map_err(|e| match e {
+                    ErrString(msg) => r.error_at(&msg, start.pos),
+                    e => e
+                })
             }
         },
-        _ => panic!("reader: unknown finish symbol; '{}'", finish)
+        _ => error(&format!("reader: unknown finish symbol: '{}'", stop))
+    }
+}
+
+// processes the \n, \" and \\ escapes of a string literal
+fn unescape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => res.push('\n'),
+                Some(e) => res.push(e),
+                None => res.push(c)
+            },
+            _ => res.push(c)
+        }
+    }
+    res
+}
+
+fn is_digits(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+// -?[0-9]+(\.[0-9]+)?([eE][-+]?[0-9]+)?
+fn is_float(s: &str) -> bool {
+    let s = s.strip_prefix('-').unwrap_or(s);
+    let (mantissa, exp) = match s.split_once(['e', 'E']) {
+        Some((m, e)) => (m, Some(e.strip_prefix(['-', '+']).unwrap_or(e))),
+        None => (s, None)
+    };
+    let mantissa_ok = match mantissa.split_once('.') {
+        Some((int, frac)) => is_digits(int) && is_digits(frac),
+        None => is_digits(mantissa)
+    };
+    mantissa_ok && exp.is_none_or(is_digits)
+}
+
+// a string token is terminated when its closing quote isn't escaped
+fn is_terminated(s: &str) -> bool {
+    let mut chars = s[1..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => { chars.next(); },
+            '"' => return true,
+            _ => {}
+        }
     }
+    false
 }
 
+// atoms are told apart by their first char, then parsed
 fn read_atom(r: &mut Reader) -> MalRet {
-    lazy_static! {
-        static ref INT: Regex = Regex::new(r"^-?[0-9]+$").unwrap();
-        static ref STR: Regex = Regex::new(r#""(?:\\.|[^\\"])*""#).unwrap();
-        static ref ID: Regex = Regex::new(r###"\d+"###).unwrap();
-    }
-    let token = r.next()?;
-    match &token[..] {
-        "nil" =>  Ok(Nil),
-        "true" => Ok(Bool(true)),
-        "false" => Ok(Bool(false)),
-        _ => {
-            if INT.is_match(&token) {
-                Ok(Int(token.parse().unwrap()))
-            } else if STR.is_match(&token) {
-                Ok(Str(token[1..token.len()-1].to_string()))
-            } else if ID.is_match(&token) {
-                Ok(Sym(token.parse().unwrap()))
-            } else if token.starts_with(":") {
-                Ok(Keyword(token[1..].to_string()))
-            } else if token.starts_with("\"") {
-                error("expected '\"', got EOF")
-            } else {
-                Ok(Sym(token.parse().unwrap()))
-                // Err(ErrString(format!("reader: Unknown token: '{}'", &token[..])))
+    let t = r.next()?;
+    let token = t.text;
+    match token {
+        "nil" =>  return Ok(Nil),
+        "true" => return Ok(Bool(true)),
+        "false" => return Ok(Bool(false)),
+        "##Inf" => return Ok(Float(f64::INFINITY)),
+        "##-Inf" => return Ok(Float(f64::NEG_INFINITY)),
+        "##NaN" => return Ok(Float(f64::NAN)),
+        _ => {}
+    }
+
+    let unsigned = token.strip_prefix('-').unwrap_or(token);
+    if unsigned.starts_with(|c: char| c.is_ascii_digit()) {
+        if is_digits(unsigned) {
+            // literals too big for an i64 are read as bignums
+            return match token.parse() {
+                Ok(i) => Ok(Int(i)),
+                Err(_) => Ok(big(token.parse().unwrap()))
+            };
+        }
+        if let Some((n, d)) = token.split_once('/') {
+            if is_digits(n.strip_prefix('-').unwrap_or(n)) && is_digits(d) {
+                // n/d literals are normalized, so 4/2 reads as 2
+                let d: BigInt = d.parse().unwrap();
+                if d.is_zero() {
+                    return Err(r.error_at("divide by zero", t.pos));
+                }
+                return Ok(ratio(BigRational::new(n.parse().unwrap(), d)));
             }
         }
+        if is_float(token) {
+            return Ok(Float(token.parse().unwrap()));
+        }
+    }
+
+    if token.starts_with('"') {
+        if !is_terminated(token) {
+            r.eof = true;
+            return Err(r.error_at("expected '\"', got EOF", t.pos));
+        }
+        return Ok(Str(unescape(&token[1..token.len()-1])));
+    }
+    match token.strip_prefix(':') {
+        Some(kw) => Ok(Keyword(kw.to_string())),
+        None => Ok(Sym(token.to_string()))
     }
 }
 
+// reading, evaluating and printing recurse once per nesting level, so
+// input like 100000 ( would overflow the stack without a limit
+const MAX_DEPTH: usize = 256;
+
 fn read_form(r: &mut Reader) -> MalRet {
-    let token = r.peek()?;
-    
-    match &token[..] {
+    if r.depth == MAX_DEPTH {
+        let t = r.peek()?;
+        return Err(r.error_at(&format!("reader: forms nested more than {} deep", MAX_DEPTH), t.pos));
+    }
+    r.depth += 1;
+    let res = read_nested(r);
+    r.depth -= 1;
+    res
+}
+
+fn read_nested(r: &mut Reader) -> MalRet {
+    let t = r.peek()?;
+
+    match t.text {
         "(" => { read_list(r) }
-        ")" => { error("reader: Unexpected character: ')'") }
+        ")" => { Err(r.error_at("reader: Unexpected character: ')'", t.pos)) }
 
         "[" => { read_list(r)}
-        "]" => { error("reader: Unexpected character: ']'") }
+        "]" => { Err(r.error_at("reader: Unexpected character: ']'", t.pos)) }
 
         "{" => { read_list(r) }
-        "}" => { error("reader: Unexpected character: '}'") }
+        "}" => { Err(r.error_at("reader: Unexpected character: '}'", t.pos)) }
 
         "~@" => {
             let _ = r.next();
@@ -163,3 +416,78 @@ fn read_form(r: &mut Reader) -> MalRet {
         _ => {read_atom(r)}
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::types::format_error;
+
+    // xorshift, so that every run tries the same inputs
+    pub(crate) struct Rng(pub u64);
+
+    impl Rng {
+        pub(crate) fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    // reader syntax and malformed bits of it
+    const SYNTAX: &[&str] = &[
+        "(", ")", "[", "]", "{", "}", "'", "`", "~", "~@", "@", "^",
+        "\"", "\"\\", "\\", ";c\n", " ", ",", "\n",
+    ];
+
+    const ATOMS: &[&str] = &[
+        "\"s\"", "0", "1", "-2", "1/0", "3/4", "-1.5e3", "1e", "1.", "##NaN", "##Inf", "99999999999999999999",
+        ":k", ":", ":a1", "nil", "true", "x", "-", "+1", "a1",
+    ];
+
+    // up to 40 tokens of syntax and atoms, spaced or not
+    pub(crate) fn soup(rng: &mut Rng, atoms: &[&str]) -> String {
+        let mut s = String::new();
+        for _ in 0..1 + rng.below(40) {
+            let tokens = if rng.below(2) == 0 { SYNTAX } else { atoms };
+            s.push_str(tokens[rng.below(tokens.len())]);
+            if rng.below(2) == 0 {
+                s.push(' ');
+            }
+        }
+        s
+    }
+
+    #[test]
+    fn reading_random_input_never_panics() {
+        let mut rng = Rng(0x2545F4914F6CDD1D);
+        for _ in 0..20_000 {
+            let s = soup(&mut rng, ATOMS);
+            let _ = read_all(s.clone());
+            let _ = read_str(s.clone());
+            let _ = is_incomplete(&s);
+        }
+    }
+
+    #[test]
+    fn incomplete_input_is_detected() {
+        for s in ["(", "(1 [2", "{:a", "'", "\"abc", "\"abc 1\n", "\"a\\\"", "(do\n  \"x 1"] {
+            assert!(is_incomplete(s), "{:?} is incomplete", s);
+        }
+        for s in ["", "1", "(1)", ":a1", "\"abc 1\"", ")", "(]", "1 )", "; (comment"] {
+            assert!(!is_incomplete(s), "{:?} is complete", s);
+        }
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error() {
+        for open in ["(", "[", "{", "'", "@"] {
+            let s = open.repeat(200_000);
+            match read_all(s.clone()) {
+                Err(e) => assert!(format_error(e).contains("nested more than")),
+                Ok(_) => panic!("read {}... without an error", open)
+            }
+            assert!(!is_incomplete(&s));
+        }
+    }
+}
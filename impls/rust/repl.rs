@@ -0,0 +1,634 @@
+use std::borrow::Cow;
+use std::io::IsTerminal;
+use std::time::Instant;
+
+use rustyline::completion::Completer;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::{Validator, ValidationContext, ValidationResult};
+use rustyline::config::{Config, Configurer, CompletionType, EditMode};
+use rustyline::error::ReadlineError;
+use rustyline::{Context, Editor, Helper};
+
+use crate::env::Env;
+use crate::reader;
+use crate::reader::{Lexer, Token, is_atom_char, is_incomplete};
+use crate::types::{MalVal, MalRet, MalErr, format_error};
+use crate::types::MalVal::{Nil, Str, Keyword, Int};
+use crate::types::MalErr::{ErrString, ErrMalVal};
+
+// ANSI colors of the highlighter
+const STRING: &str = "\x1b[32m";
+const KEYWORD: &str = "\x1b[35m";
+const NUMBER: &str = "\x1b[36m";
+const SPECIAL_FORM: &str = "\x1b[1;33m";
+const MATCHING_BRACKET: &str = "\x1b[1;34m";
+const RESET: &str = "\x1b[0m";
+
+// line editing for the REPL:
+// - Tab completes the symbols bound in the env (outer scopes included)
+//   and the special forms of the step
+// - strings, keywords, numbers, special forms and the bracket matching the
+//   one at the cursor are colored, unless stdout isn't a terminal
+// - Enter on an unfinished form starts a new line instead of submitting it,
+//   except in dumb terminals (like the test runner's) where an unbalanced
+//   line is an error right away; read_form covers input that doesn't go
+//   through the editor
+pub struct MalHelper {
+    env: Env,
+    special_forms: &'static [&'static str],
+    colors: bool,
+    multiline: bool
+}
+
+impl MalHelper {
+    pub fn new(env: Env, special_forms: &'static [&'static str]) -> MalHelper {
+        MalHelper {
+            env,
+            special_forms,
+            colors: std::io::stdout().is_terminal(),
+            multiline: interactive()
+        }
+    }
+
+    fn color(&self, token: &str) -> Option<&'static str> {
+        let mut chars = token.chars();
+        match (chars.next(), chars.next()) {
+            (Some('"'), _) => Some(STRING),
+            (Some(':'), _) => Some(KEYWORD),
+            (Some(c), _) if c.is_ascii_digit() => Some(NUMBER),
+            (Some('-'), Some(c)) if c.is_ascii_digit() => Some(NUMBER),
+            (Some('#'), Some('#')) => Some(NUMBER),
+            _ if self.special_forms.contains(&token) => Some(SPECIAL_FORM),
+            _ => None
+        }
+    }
+}
+
+// an editor completing from env, that lists the candidates on Tab like bash
+pub fn editor(env: Env, special_forms: &'static [&'static str], settings: &Settings) -> Editor<MalHelper> {
+    let config = Config::builder()
+        .completion_type(CompletionType::List)
+        .max_history_size(settings.history_size)
+        .edit_mode(settings.edit_mode)
+        .build();
+    let mut rl = Editor::with_config(config);
+    rl.set_helper(Some(MalHelper::new(env, special_forms)));
+    rl
+}
+
+impl Completer for MalHelper {
+    type Candidate = String;
+
+    // completes the symbol that ends at the cursor, sorted and without duplicates
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let word = line[..pos].rsplit(|c| !is_atom_char(c)).next().unwrap_or("");
+        // ~, ^ and @ can start a token on their own, as in ~@xs or @a
+        let prefix = word.trim_start_matches(['~', '^', '@']);
+        let start = pos - prefix.len();
+
+        let mut candidates: Vec<String> = self.env.symbols().into_iter()
+            .chain(self.special_forms.iter().map(|s| s.to_string()))
+            .filter(|s| s.starts_with(prefix))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for MalHelper {
+    type Hint = String;
+}
+
+// the offset of the bracket matching the one at the cursor, or just before it
+fn matching_bracket(tokens: &[Token], pos: usize) -> Option<usize> {
+    let mut open: Vec<usize> = vec![];
+    let mut pairs: Vec<(usize, usize)> = vec![];
+    for t in tokens {
+        match t.text {
+            "(" | "[" | "{" => open.push(t.pos.offset),
+            ")" | "]" | "}" => if let Some(o) = open.pop() {
+                pairs.push((o, t.pos.offset));
+            },
+            _ => {}
+        }
+    }
+    let partner = |at: usize| pairs.iter().find_map(|&(o, c)| {
+        if o == at { Some(c) } else if c == at { Some(o) } else { None }
+    });
+    partner(pos).or_else(|| pos.checked_sub(1).and_then(partner))
+}
+
+impl Highlighter for MalHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        if !self.colors {
+            return Cow::Borrowed(line);
+        }
+        let tokens: Vec<Token> = Lexer::new(line).collect();
+        let bracket = matching_bracket(&tokens, pos);
+
+        let mut res = String::with_capacity(line.len() * 2);
+        let mut last = 0;
+        for t in tokens.iter() {
+            let start = t.pos.offset;
+            // whitespace and comments between tokens are kept as they are
+            res.push_str(&line[last..start]);
+            let color = if Some(start) == bracket { Some(MATCHING_BRACKET) } else { self.color(t.text) };
+            match color {
+                Some(c) => {
+                    res.push_str(c);
+                    res.push_str(t.text);
+                    res.push_str(RESET);
+                },
+                None => res.push_str(t.text)
+            }
+            last = start + t.text.len();
+        }
+        res.push_str(&line[last..]);
+        Cow::Owned(res)
+    }
+
+    // the matching bracket moves with the cursor
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        self.colors
+    }
+}
+
+impl Validator for MalHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if self.multiline && is_incomplete(ctx.input()) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+impl Helper for MalHelper {}
+
+const HELP: &str = "\
+:help          show this help
+:env           list the bindings of the REPL env
+:load <file>   evaluate every form of file
+:time <expr>   evaluate expr and report how long it took
+:reset         start over with a fresh env, evaluating the init file again
+:quit          leave the REPL (as does Ctrl-D)
+
+*1, *2 and *3 are the last three results, *e the last error.
+The init file, $MAL_INIT or else ~/.malrc.mal, is evaluated at startup
+and can change the REPL settings, e.g. (def! *repl-edit-mode* :vi):
+*repl-prompt*, *repl-history-file* (nil for none), *repl-history-size*
+and *repl-edit-mode* (:emacs or :vi); :reset applies changes to them.
+Steps 0 to 2 have no env to hold settings and always use the defaults.";
+
+// the settings and their defaults
+const SETTINGS: &[(&str, &str)] = &[
+    ("*repl-prompt*", "\"user> \""),
+    ("*repl-history-file*", "\".mal-history\""),
+    ("*repl-history-size*", "100"),
+    ("*repl-edit-mode*", ":emacs"),
+];
+
+// the parts of a step that the REPL drives
+pub struct Step {
+    // builds the env the REPL starts with, again on :reset
+    pub env: fn() -> Env,
+    pub read: fn(&str) -> Result<Vec<MalVal>, MalErr>,
+    pub eval: fn(&MalVal, &Env) -> MalRet,
+    pub print: fn(&MalVal) -> Result<String, MalErr>,
+    // the special forms, which eval handles itself, for completion
+    pub special_forms: &'static [&'static str],
+}
+
+// what the REPL does after a meta-command
+pub enum Flow {
+    Continue,
+    Quit
+}
+
+// the REPL settings, read from the env once the init file has run
+pub struct Settings {
+    pub prompt: String,
+    // shown while a form continues over several lines, as wide as prompt
+    pub continuation: String,
+    history_file: Option<String>,
+    history_size: usize,
+    edit_mode: EditMode,
+    // history save failures are reported once, not after every line
+    history_warned: bool
+}
+
+impl Settings {
+    pub fn from_env(env: &Env) -> Settings {
+        let get = |sym: &str| env.get(sym.to_string()).unwrap_or(Nil);
+
+        let prompt = match &get("*repl-prompt*") {
+            Str(s) => s.to_string(),
+            v => setting_warning("*repl-prompt*", v, "user> ".to_string())
+        };
+        let history_file = match &get("*repl-history-file*") {
+            Nil => None,
+            Str(s) => Some(expand_home(s)),
+            v => setting_warning("*repl-history-file*", v, Some(".mal-history".to_string()))
+        };
+        let history_size = match &get("*repl-history-size*") {
+            Int(n) if *n >= 0 => *n as usize,
+            v => setting_warning("*repl-history-size*", v, 100)
+        };
+        let edit_mode = match &get("*repl-edit-mode*") {
+            Str(s) | Keyword(s) if s == "emacs" => EditMode::Emacs,
+            Str(s) | Keyword(s) if s == "vi" => EditMode::Vi,
+            v => setting_warning("*repl-edit-mode*", v, EditMode::Emacs)
+        };
+
+        Settings::new(prompt, history_file, history_size, edit_mode)
+    }
+
+    fn new(prompt: String, history_file: Option<String>, history_size: usize, edit_mode: EditMode) -> Settings {
+        let width = prompt.chars().count().max(4);
+        Settings {
+            continuation: format!("{:>w$} ", "...", w = width - 1),
+            prompt,
+            history_file,
+            history_size,
+            edit_mode,
+            history_warned: false
+        }
+    }
+
+    pub fn load_history<H: Helper>(&self, rl: &mut Editor<H>) {
+        if let Some(file) = &self.history_file {
+            if rl.load_history(file).is_err() {
+                eprintln!("No previous history.");
+            }
+        }
+    }
+
+    // switches an editor set up with old over to these settings
+    fn apply(&self, old: &Settings, rl: &mut Editor<MalHelper>) {
+        rl.set_max_history_size(self.history_size);
+        rl.set_edit_mode(self.edit_mode);
+        if self.history_file != old.history_file {
+            rl.clear_history();
+            self.load_history(rl);
+        }
+    }
+
+    pub fn save_history<H: Helper>(&mut self, rl: &mut Editor<H>) {
+        if let Some(file) = &self.history_file {
+            if let Err(e) = rl.save_history(file) {
+                if !self.history_warned {
+                    eprintln!("Warning: can't save history to {}: {}", file, e);
+                    self.history_warned = true;
+                }
+            }
+        }
+    }
+}
+
+// the settings of steps without an env to change them in
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings::new("user> ".to_string(), Some(".mal-history".to_string()), 100, EditMode::Emacs)
+    }
+}
+
+fn setting_warning<T>(sym: &str, val: &MalVal, default: T) -> T {
+    eprintln!("Warning: ignoring {} {}", sym, val.pr_str(true));
+    default
+}
+
+// ~/ is the home directory, as in a shell
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home, rest),
+        _ => path.to_string()
+    }
+}
+
+// the init file: $MAL_INIT, or ~/.malrc.mal which doesn't have to exist
+fn init_file() -> Option<(String, bool)> {
+    match std::env::var("MAL_INIT") {
+        Ok(file) => Some((file, true)),
+        Err(_) => std::env::var("HOME").ok().map(|home| (format!("{}/.malrc.mal", home), false))
+    }
+}
+
+// readies env for the REPL: the result history is nil until there's
+// something to remember, the settings get their defaults, and then the
+// init file is evaluated, so that it can change them
+pub fn prepare(env: &Env, step: &Step) {
+    for sym in ["*1", "*2", "*3", "*e"] {
+        let _ = env.set(sym.to_string(), Nil);
+    }
+    for (sym, default) in SETTINGS {
+        if let Ok(val) = reader::read_str(default.to_string()) {
+            let _ = env.set(sym.to_string(), val);
+        }
+    }
+    if let Some((file, required)) = init_file() {
+        if required || std::path::Path::new(&file).exists() {
+            if let Err(e) = load(&file, env, step.eval) {
+                eprintln!("Warning: init file failed: {}", format_error(e));
+            }
+        }
+    }
+}
+
+// *1 is the last result, the ones before it move to *2 and *3, Clojure style
+fn remember(env: &Env, val: &MalVal) {
+    let _ = env.set("*3".to_string(), env.get("*2".to_string()).unwrap_or(Nil));
+    let _ = env.set("*2".to_string(), env.get("*1".to_string()).unwrap_or(Nil));
+    let _ = env.set("*1".to_string(), val.clone());
+}
+
+// *e is the last error: what was thrown, or the message of a built-in error
+fn remember_error(env: &Env, e: MalErr) -> MalErr {
+    let val = match &e {
+        ErrString(s) => Str(s.to_string()),
+        ErrMalVal(mv) => mv.clone(),
+    };
+    let _ = env.set("*e".to_string(), val);
+    e
+}
+
+// the REPL's read-eval-print: every form of line is evaluated in order and
+// its result remembered, so that it can be used again as *1, *2 or *3
+pub fn rep(line: &str, env: &Env, step: &Step) -> Result<String, MalErr> {
+    let mut out = vec![];
+    for ast in (step.read)(line).map_err(|e| remember_error(env, e))? {
+        let exp = (step.eval)(&ast, env).map_err(|e| remember_error(env, e))?;
+        remember(env, &exp);
+        out.push((step.print)(&exp).map_err(|e| remember_error(env, e))?);
+    }
+    Ok(out.join("\n"))
+}
+
+// the meta-commands, as HELP lists them
+const COMMANDS: &[&str] = &["help", "env", "load", "time", "reset", "quit"];
+
+// the command name is one typo away from: a letter added, dropped,
+// changed or two neighbours swapped, as in :hlep or :quti
+fn near_command(name: &str) -> Option<&'static str> {
+    let a: Vec<char> = name.chars().collect();
+    COMMANDS.iter().copied().find(|cmd| {
+        let b: Vec<char> = cmd.chars().collect();
+        let (short, long) = if a.len() <= b.len() { (&a, &b) } else { (&b, &a) };
+        let start = short.iter().zip(long.iter()).take_while(|(x, y)| x == y).count();
+        let end = short[start..].iter().rev().zip(long[start..].iter().rev()).take_while(|(x, y)| x == y).count();
+        let (s, l) = (&short[start..short.len() - end], &long[start..long.len() - end]);
+        match (s.len(), l.len()) {
+            (0, 1) | (1, 1) => true,
+            (2, 2) => s[0] == l[1] && s[1] == l[0],
+            _ => false
+        }
+    })
+}
+
+// runs line as a REPL meta-command like :load or :time, or returns None
+// when it's mal to be evaluated: a lone keyword like :foo is still mal,
+// only a known command or an unknown one with arguments is taken over
+pub fn command(line: &str, env: &mut Env, rl: &mut Editor<MalHelper>, settings: &mut Settings, step: &Step) -> Option<Flow> {
+    let rest = line.strip_prefix(':')?;
+    let (name, arg) = match rest.split_once(char::is_whitespace) {
+        Some((name, arg)) => (name, arg.trim()),
+        None => (rest, "")
+    };
+
+    match name {
+        "help" | "env" | "reset" | "quit" if !arg.is_empty() => println!("Error: :{} takes no arguments", name),
+        "help" => println!("{}", HELP),
+        "env" => print_env(env),
+        "load" if arg.is_empty() => println!("Error: :load needs a file"),
+        "load" => {
+            // the file can be given as a mal string too
+            let file = arg.strip_prefix('"').and_then(|f| f.strip_suffix('"')).unwrap_or(arg);
+            if let Err(e) = load(file, env, step.eval) {
+                println!("Error: {}", format_error(e));
+            }
+        },
+        "time" if arg.is_empty() => println!("Error: :time needs an expression"),
+        "time" => {
+            let start = Instant::now();
+            let res = rep(arg, env, step);
+            let elapsed = start.elapsed();
+            match res {
+                Ok(out) if out.is_empty() => {},
+                Ok(out) => println!("{}", out),
+                Err(e) => println!("Error: {}", format_error(e)),
+            }
+            println!("Elapsed time: {:.3} msecs", elapsed.as_secs_f64() * 1000.0);
+        },
+        "reset" => {
+            *env = (step.env)();
+            prepare(env, step);
+            if let Some(h) = rl.helper_mut() {
+                h.env = env.clone();
+            }
+            // the init file may have changed the settings since startup
+            let old = std::mem::replace(settings, Settings::from_env(env));
+            settings.apply(&old, rl);
+        },
+        "quit" => return Some(Flow::Quit),
+        _ => match (near_command(name), arg.is_empty()) {
+            // evaluated as a keyword, but likely meant as a command
+            (Some(cmd), true) => {
+                println!("Note: :{} isn't a command, did you mean :{}?", name, cmd);
+                return None;
+            },
+            (None, true) => return None,
+            (Some(cmd), false) => println!("Unknown command :{}, did you mean :{}?", name, cmd),
+            (None, false) => println!("Unknown command :{}, :help lists the commands", name),
+        },
+    }
+    Some(Flow::Continue)
+}
+
+fn load(file: &str, env: &Env, eval: fn(&MalVal, &Env) -> MalRet) -> Result<(), MalErr> {
+    let src = std::fs::read_to_string(file)
+        .map_err(|e| ErrString(format!("{}: {}", file, e)))?;
+    for ast in reader::read_all_from(src, Some(file.to_string()))? {
+        eval(&ast, env)?;
+    }
+    Ok(())
+}
+
+// one binding per line, sorted by name
+fn print_env(env: &Env) {
+    let mut names = env.symbols();
+    names.sort();
+    names.dedup();
+    let width = names.iter().map(|n| n.len()).max().unwrap_or(0);
+    for name in names {
+        if let Ok(val) = env.get(name.clone()) {
+            println!("{:<width$} {}", name, val.pr_str(true), width = width);
+        }
+    }
+}
+
+// forms can span lines, typed or piped in, except in dumb terminals
+// (like the test runner's) which get one form per line, so an unbalanced
+// line is an error right away
+fn multiline() -> bool {
+    !matches!(std::env::var("TERM").as_deref(), Ok("dumb"))
+}
+
+// whether lines go through the editor's validator; rustyline validates
+// piped lines too, but drops an unfinished form at the end of the input,
+// so read_form joins those instead
+fn interactive() -> bool {
+    std::io::stdin().is_terminal() && multiline()
+}
+
+// reads a form and adds it to the history; None at the end of the input.
+// In a terminal the editor's validator keeps an unfinished form open, so
+// lines only need joining here, behind the continuation prompt, when they
+// don't go through the editor, as when stdin is a pipe
+pub fn read_form<H: Helper>(rl: &mut Editor<H>, settings: &mut Settings) -> Option<String> {
+    let multiline = multiline();
+    // the lines read so far of a form that isn't complete yet
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() { &settings.prompt } else { &settings.continuation };
+        match rl.readline(prompt) {
+            Ok(line) => {
+                input.push_str(&line);
+                input.push('\n');
+                if multiline && is_incomplete(&input) {
+                    continue;
+                }
+            },
+            // Ctrl-C drops the form being typed
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            },
+            // the input ended inside a form: reading what there is of it
+            // reports what's missing
+            Err(ReadlineError::Eof) if !input.is_empty() => {},
+            Err(ReadlineError::Eof) => return None,
+            Err(err) => {
+                println!("Error: {:?}", err);
+                return None;
+            }
+        }
+        let form = input.trim_end().to_string();
+        rl.add_history_entry(&form);
+        settings.save_history(rl);
+        return Some(form);
+    }
+}
+
+// the stack of the thread eval runs on: deep enough for types::MAX_NESTING
+// levels of eval or printing even in a debug build, where a level of eval
+// takes several KB
+pub const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+// runs f on a thread with the stack deep recursion in mal needs, as the
+// main thread's is too small to reach MAX_NESTING
+pub fn with_stack(f: fn()) {
+    let thread = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(f)
+        .expect("can't start the REPL thread");
+    if thread.join().is_err() {
+        std::process::exit(101);
+    }
+}
+
+// the REPL of the steps with an env: reads forms until the end of the
+// input or :quit, runs meta-commands and evaluates the rest
+pub fn run(mut env: Env, step: &Step) {
+    prepare(&env, step);
+    let mut settings = Settings::from_env(&env);
+    let mut rl = editor(env.clone(), step.special_forms, &settings);
+    settings.load_history(&mut rl);
+
+    while let Some(form) = read_form(&mut rl, &mut settings) {
+        match command(&form, &mut env, &mut rl, &mut settings, step) {
+            Some(Flow::Quit) => break,
+            Some(Flow::Continue) => continue,
+            None => {}
+        }
+        if form.is_empty() {
+            continue;
+        }
+        match rep(&form, &env, step) {
+            Ok(out) if out.is_empty() => {},
+            Ok(out) => println!("{}", out),
+            Err(e) => println!("Error: {}", format_error(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::make_env;
+    use rustyline::history::History;
+
+    fn helper() -> MalHelper {
+        let env = make_env(None);
+        for sym in ["deref", "do", "seq", "swap!"] {
+            let _ = env.set(sym.to_string(), Nil);
+        }
+        MalHelper { env, special_forms: &["def!", "do", "if"], colors: true, multiline: true }
+    }
+
+    fn matching(line: &str, pos: usize) -> Option<usize> {
+        let tokens: Vec<Token> = Lexer::new(line).collect();
+        matching_bracket(&tokens, pos)
+    }
+
+    #[test]
+    fn brackets_match_at_or_just_before_the_cursor() {
+        assert_eq!(matching("(a [b] c)", 0), Some(8));
+        assert_eq!(matching("(a [b] c)", 8), Some(0));
+        assert_eq!(matching("(a [b] c)", 9), Some(0));
+        assert_eq!(matching("(a [b] c)", 3), Some(5));
+        assert_eq!(matching("(a [b] c)", 6), Some(3));
+        assert_eq!(matching("(a [b] c)", 7), None);
+        assert_eq!(matching("(a [b", 0), None);
+        assert_eq!(matching("a)", 1), None);
+        assert_eq!(matching("\"(\" )", 4), None);
+    }
+
+    #[test]
+    fn typos_of_commands_are_recognised() {
+        for (typo, cmd) in [("hlep", "help"), ("quti", "quit"), ("en", "env"), ("loads", "load"), ("tine", "time")] {
+            assert_eq!(near_command(typo), Some(cmd), "{}", typo);
+        }
+        for name in ["", "e", "kw", "hepl2", "exit", "environment"] {
+            assert_eq!(near_command(name), None, "{}", name);
+        }
+    }
+
+    fn complete(line: &str) -> (usize, Vec<String>) {
+        let history = History::new();
+        helper().complete(line, line.len(), &Context::new(&history)).unwrap()
+    }
+
+    #[test]
+    fn completion_skips_reader_macros() {
+        assert_eq!(complete("(de"), (1, vec!["def!".to_string(), "deref".to_string()]));
+        assert_eq!(complete("(~@se"), (3, vec!["seq".to_string()]));
+        assert_eq!(complete("@sw"), (1, vec!["swap!".to_string()]));
+        assert_eq!(complete("(d"), (1, vec!["def!".to_string(), "deref".to_string(), "do".to_string()]));
+        assert_eq!(complete("(x"), (1, vec![]));
+    }
+
+    #[test]
+    fn highlighting_colors_tokens_and_the_matching_bracket() {
+        let h = helper();
+        assert_eq!(h.highlight("(if x)", 0), format!("({}if{} x{}){}", SPECIAL_FORM, RESET, MATCHING_BRACKET, RESET));
+        // so does the bracket just before the cursor
+        assert_eq!(h.highlight("(if x)", 6), format!("{}({}{}if{} x)", MATCHING_BRACKET, RESET, SPECIAL_FORM, RESET));
+        assert_eq!(h.highlight("[1 :k] ;c", 3), format!("[{}1{} {}:k{}] ;c", NUMBER, RESET, KEYWORD, RESET));
+        // unbalanced input is colored as far as it goes
+        assert_eq!(h.highlight("(str \"a", 0), format!("(str {}\"a{}", STRING, RESET));
+        assert_eq!(h.highlight(")) -1", 0), format!(")) {}-1{}", NUMBER, RESET));
+        let plain = MalHelper { colors: false, ..helper() };
+        assert_eq!(plain.highlight("(if x)", 0), "(if x)");
+    }
+}
@@ -4,26 +4,30 @@ use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
 fn read(input: String) -> String {
-    return input
+    input
 }
 
 
 fn eval(ast: String) -> String {
-    return ast;
+    ast
 }
 
 fn print(eval: String) {
-    if eval.len() > 0 {
+    if !eval.is_empty() {
         println!("{}", eval);
     }
 }
 
 fn main() {
+    // step0 reads no forms, so it has neither repl.rs nor its settings:
+    // the history file and prompt are always the defaults
     // `()` can be used when no completer is required
     let mut rl = Editor::<()>::new();
     if rl.load_history(".mal-history").is_err() {
         eprintln!("No previous history.");
     }
+    // a history file that can't be saved is reported once, not on every line
+    let mut history_warned = false;
 
     loop {
 
@@ -31,7 +35,12 @@ fn main() {
         match readline {
             Ok(line) => {
                 rl.add_history_entry(&line);
-                rl.save_history(".mal-history").unwrap();
+                if let Err(e) = rl.save_history(".mal-history") {
+                    if !history_warned {
+                        eprintln!("Warning: can't save history to .mal-history: {}", e);
+                        history_warned = true;
+                    }
+                }
                 
                 let s = read(line);
                 let e = eval(s);
@@ -1,12 +1,11 @@
-extern crate regex;
 extern crate rustyline;
 extern crate fnv;
-extern crate lazy_static;
+extern crate num_bigint;
+extern crate num_traits;
+extern crate num_rational;
 
-use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-use crate::types::{MalVal, MalRet};
 use crate::types::format_error;
 
 #[macro_use]
@@ -19,36 +18,27 @@ mod reader;
 #[allow(unused_variables)]
 #[allow(unused_imports)]
 mod printer;
+#[allow(dead_code)]
+#[allow(unused_imports)]
+mod env;
+#[allow(dead_code)]
+mod repl;
 
 fn main() {
+    let mut settings = repl::Settings::default();
     // `()` can be used when no completer is required
     let mut rl = Editor::<()>::new();
-    if rl.load_history(".mal-history").is_err() {
-        eprintln!("No previous history.");
-    }
+    settings.load_history(&mut rl);
 
-    loop {
-
-        let readline = rl.readline("user> ");
-        match readline {
-            Ok(line) => {
-                rl.add_history_entry(&line);
-                rl.save_history(".mal-history").unwrap();
-
-                if line.len() > 0 {
-                    match reader::read_str(line) {
-                        Ok(mv) => {
-                            println!("{}", mv.pr_str());
-                        }
-                        Err(e) => println!("Error: {}", format_error(e)),
+    while let Some(form) = repl::read_form(&mut rl, &mut settings) {
+        if !form.is_empty() {
+            match reader::read_all(form) {
+                Ok(forms) => {
+                    for mv in forms {
+                        println!("{}", mv.pr_str(true));
                     }
                 }
-            }
-            Err(ReadlineError::Interrupted) => continue,
-            Err(ReadlineError::Eof) => break,
-            Err(err) => {
-                println!("Error: {:?}", err);
-                break;
+                Err(e) => println!("Error: {}", format_error(e)),
             }
         }
     }
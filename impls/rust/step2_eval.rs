@@ -1,17 +1,17 @@
-extern crate regex;
 extern crate rustyline;
 extern crate fnv;
-extern crate lazy_static;
+extern crate num_bigint;
+extern crate num_traits;
+extern crate num_rational;
 
-use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use fnv::FnvHashMap;
 use std::rc::Rc;
 
-use crate::types::MalErr::{ErrString, ErrMalVal};
+use crate::types::MalErr::ErrString;
 use crate::types::{MalVal, MalArgs, MalRet, MalErr, MapKey};
-use crate::types::MalVal::{Func, Int, Sym, List, Vector, Hash, Nil};
-use crate::types::{error, format_error};
+use crate::types::MalVal::{Func, Sym, List, Vector, Hash, Nil};
+use crate::types::{error, format_error, func};
 
 #[macro_use]
 #[allow(dead_code)]
@@ -23,12 +23,19 @@ mod reader;
 #[allow(unused_variables)]
 #[allow(unused_imports)]
 mod printer;
+#[allow(dead_code)]
+#[allow(unused_imports)]
+mod env;
+#[allow(dead_code)]
+mod repl;
+#[allow(dead_code)]
+mod number;
 
 pub type Env = FnvHashMap<String, MalVal>;
 
 // read
-fn read(str: &str) -> MalRet {
-    reader::read_str(str.to_string())
+fn read(str: &str) -> Result<Vec<MalVal>, MalErr> {
+    reader::read_all(str.to_string())
 }
 
 // eval
@@ -74,7 +81,7 @@ fn eval(ast: &MalVal, env: &Env) -> MalRet {
     match ast {
         // eval toplevel form
         List(v, _) => {
-            if v.len() == 0 {
+            if v.is_empty() {
                 return Ok(ast.clone())
             }
             // evaluate each list item individually
@@ -83,11 +90,11 @@ fn eval(ast: &MalVal, env: &Env) -> MalRet {
 
             // now we should be able to apply the function
             // remember that in MAL, list are `(<fn> arg1 ... argN)`
-            match evaluated {
+            match &evaluated {
                 List(v, _) => {
                     let (fcall, fargs) = v.split_at(1);
                     match fcall {
-                        [Func(f)] => (*f)(fargs.to_vec()),
+                        [Func(f, _)] => (*f)(fargs.to_vec()),
                         _ => error(&format!("can't apply: {:?}", fcall)),
                     }
                     // Ok(Sym(format!("{:?} => {:?}", fcall, fargs)))
@@ -95,75 +102,57 @@ fn eval(ast: &MalVal, env: &Env) -> MalRet {
                 _ => error("expected a list"),
             }
         },
-        rst => eval_ast(&rst, &env)
+        rst => eval_ast(rst, env)
     }
 }
 
 // print
 fn print(ast: &MalVal) -> String {
-    ast.pr_str()
+    ast.pr_str(true)
 }
 
+// evaluates every form in str, in order, printing one result per line
 fn rep(str: &str, env: &Env) -> Result<String, MalErr> {
-    let ast = read(str)?;
-    let exp = eval(&ast, env)?;
-    Ok(print(&exp))
+    let mut out = vec![];
+    for ast in read(str)? {
+        let exp = eval(&ast, env)?;
+        out.push(print(&exp));
+    }
+    Ok(out.join("\n"))
 }
 
-fn op(args: MalArgs, f: fn(i1: i64, i2: i64) -> i64) -> MalRet {
-    if args.len() == 0 || args.len() < 2 {
+fn op(args: MalArgs, f: fn(&MalVal, &MalVal) -> MalRet) -> MalRet {
+    if args.len() < 2 {
         return Err(ErrString(format!("Insufficient arguments: {} ", args.len())));
     }
-    // println!("eval {:?}", args);
-    let res = args
-                .iter()
-                .map(|mv| match &mv {
-                    Int(i) => *i,
-                    _      => panic!("Eval op {:?}; cannot eval datatype", args)
-                })
-                .reduce(f)
-                .map(|i| Int(i))
-                .ok_or(ErrString(format!("Could not apply op: {:?} ", args)));
-    return res;
+    let mut acc = args[0].clone();
+    for mv in args[1..].iter() {
+        acc = f(&acc, mv)?;
+    }
+    Ok(acc)
 }
 
 
 fn main() {
+    let mut settings = repl::Settings::default();
     // `()` can be used when no completer is required
     let mut rl = Editor::<()>::new();
-    if rl.load_history(".mal-history").is_err() {
-        eprintln!("No previous history.");
-    }
+    settings.load_history(&mut rl);
 
     // prepare Env
     let mut env = Env::default();
-    env.insert("+".to_string(), Func(|args: MalArgs| op(args, |a,b| a+b)));
-    env.insert("-".to_string(), Func(|args: MalArgs| op(args, |a,b| a-b)));
-    env.insert("*".to_string(), Func(|args: MalArgs| op(args, |a,b| a*b)));
-    env.insert("/".to_string(), Func(|args: MalArgs| op(args, |a,b| a/b)));
-
-    loop {
-
-        // read
-        let readline = rl.readline("user> ");
-        match readline {
-            Ok(line) => {
-                rl.add_history_entry(&line);
-                rl.save_history(".mal-history").unwrap();
-
-                if line.len() > 0 {
-                    let res = rep(&line, &env);
-                    match res {
-                        Ok(out) => println!("{}", out),
-                        Err(e) => println!("Error: {}", format_error(e)),
-                    }
-                }
-            }
-            Err(ReadlineError::Interrupted) => continue,
-            Err(ReadlineError::Eof) => break,
-            Err(err) => {
-                println!("Error: {:?}", err);
-                break;
+    env.insert("+".to_string(), func(|args: MalArgs| op(args, number::add)));
+    env.insert("-".to_string(), func(|args: MalArgs| op(args, number::sub)));
+    env.insert("*".to_string(), func(|args: MalArgs| op(args, number::mul)));
+    env.insert("/".to_string(), func(|args: MalArgs| op(args, number::div)));
+
+    while let Some(form) = repl::read_form(&mut rl, &mut settings) {
+        if !form.is_empty() {
+            let res = rep(&form, &env);
+            match res {
+                Ok(out) if out.is_empty() => {},
+                Ok(out) => println!("{}", out),
+                Err(e) => println!("Error: {}", format_error(e)),
             }
         }
     }
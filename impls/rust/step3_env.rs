@@ -1,10 +1,9 @@
-extern crate regex;
 extern crate rustyline;
 extern crate fnv;
-extern crate lazy_static;
+extern crate num_bigint;
+extern crate num_traits;
+extern crate num_rational;
 
-use rustyline::error::ReadlineError;
-use rustyline::Editor;
 use fnv::FnvHashMap;
 use std::rc::Rc;
 
@@ -14,10 +13,10 @@ use std::rc::Rc;
 #[allow(dead_code)]
 #[allow(unused_imports)]
 mod types;
-use crate::types::MalErr::{ErrString, ErrMalVal};
+use crate::types::MalErr::ErrString;
 use crate::types::{MalVal, MalArgs, MalRet, MalErr, MapKey};
-use crate::types::MalVal::{Func, Int, Sym, List, Vector, Hash, Nil};
-use crate::types::{error, format_error};
+use crate::types::MalVal::{Func, Sym, List, Vector, Hash, Nil};
+use crate::types::{error, func};
 
 #[allow(dead_code)]
 #[allow(unused_imports)]
@@ -27,17 +26,27 @@ mod reader;
 #[allow(unused_imports)]
 mod printer;
 
+#[allow(dead_code)]
 #[allow(unused_imports)]
 mod env;
+#[allow(dead_code)]
+mod number;
 use crate::env::{Env, make_env};
 
+mod repl;
+
+// special forms, which eval handles itself
+const SPECIAL_FORMS: &[&str] = &[
+    "def!", "let*"
+];
+
 // read
-fn read(str: &str) -> MalRet {    
-    reader::read_str(str.to_string())
+fn read(str: &str) -> Result<Vec<MalVal>, MalErr> {
+    reader::read_all(str.to_string())
 }
 
 // eval
-fn eval_ast(ast: &MalVal, env: &mut Env) -> MalRet {
+fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
     // println!("eval_ast {:?}", ast);
     match ast {
         Sym(s) => env.get(s.to_string()),
@@ -72,11 +81,11 @@ fn eval_ast(ast: &MalVal, env: &mut Env) -> MalRet {
 }
 
 // toplevel eval
-fn eval(ast: &MalVal, env: &mut Env) -> MalRet {    
+fn eval(ast: &MalVal, env: &Env) -> MalRet {    
     match ast {
         // eval toplevel form: if it's a list it can be multiple things
         List(v, _) => {
-            if v.len() == 0 {
+            if v.is_empty() {
                 return Ok(ast.clone())
             }
             
@@ -84,21 +93,24 @@ fn eval(ast: &MalVal, env: &mut Env) -> MalRet {
             match first {
                 // (def! binding val)
                 Sym(s) if s == "def!" => {                            
-                    let binding = v.get(1).ok_or(ErrString(format!("No binding for expression: {:?}", v))).unwrap();
-                    let val = v.get(2).ok_or(ErrString(format!("No value for expression: {:?}", v))).unwrap();
+                    let binding = v.get(1).ok_or_else(|| ErrString(format!("No binding for expression: {}", ast.pr_str(true))))?;
+                    if !matches!(binding, Sym(_)) {
+                        return error("def!: binding is not a symbol");
+                    }
+                    let val = v.get(2).ok_or_else(|| ErrString(format!("No value for expression: {}", ast.pr_str(true))))?;
                     let bindval = eval(val, env)?;
                     env.set(binding.to_string(), bindval.clone())?;
                                                 
                     // println!("sym: {:?} binding {:?}", binding, val);
-                    return Ok(bindval);
+                    Ok(bindval)
                 },
                 // (let* [binding1 val1
                 //        bindingN valN] 
                 //    body)
                 Sym(s) if s == "let*" => {
-                    let bindings = v.get(1).ok_or(ErrString(format!("No bindings for expression: {:?}", v))).unwrap();
-                    let body = v.get(2).ok_or(Nil).unwrap(); // let can have empty bindings
-                    let mut let_env = make_env(Some(env.clone()));
+                    let bindings = v.get(1).ok_or_else(|| ErrString(format!("No bindings for expression: {}", ast.pr_str(true))))?;
+                    let body = v.get(2).cloned().unwrap_or(Nil); // let can have empty bindings
+                    let let_env = make_env(Some(env.clone()));
 
                     // TODO bindings should be a List of (sym, something, sym something)
                     // body can be anything
@@ -108,37 +120,36 @@ fn eval(ast: &MalVal, env: &mut Env) -> MalRet {
                     match bindings {
                         List(bindvec, _) | Vector(bindvec, _) => {
                             if bindvec.len() %2 != 0 {
-                                return error(&format!("let* bindings: mismatch"))
+                                return error("let* bindings: mismatch")
                             }
 
                             // create bindings
                             for pair in bindvec.chunks(2) {
                                 match pair {
                                     [Sym(binding), expr] => {
-                                        let res = eval(expr, &mut let_env)?;
+                                        let res = eval(expr, &let_env)?;
                                         let_env.set(binding.to_string(), res)?;
                                     },
-                                    _ => return error(&format!("let* bindings: binding is not a symbol"))
+                                    _ => return error("let* bindings: binding is not a symbol")
                                 }
                             }
 
                             // evaluate body with inner env
                             // println!("Gonna evaluate body {:?} with env {:?}", body, let_env);
-                            return eval(body, &mut let_env);
+                            eval(&body, &let_env)
                         },
-                        _  => error(&format!("let* bindings: not a list"))
+                        _  => error("let* bindings: not a list")
                     }
                 },
 
                 // regular function call
                 // (+ 1 1)
-                _ => match eval_ast(ast, env)? {
+                _ => match &eval_ast(ast, env)? {
                     List(v, _) => {
                         let (fcall, fargs) = v.split_at(1);
                         match fcall {
-                            [Func(f)]  => return (*f)(fargs.to_vec()),
-                            [Sym(unk)] => error(&format!("'{:}' not found", unk)),
-                            _          => error(&format!("'{:?}' not found", fcall.get(0))),
+                            [Func(f, _)]  => (*f)(fargs.to_vec()),
+                            _          => error(&format!("'{}' is not a function", fcall[0].pr_str(true))),
                         }
                     },
                     _ => error("Expected a list")
@@ -146,76 +157,43 @@ fn eval(ast: &MalVal, env: &mut Env) -> MalRet {
             }
         },
         // if toplevel is not a list, evaluate its AST
-        rst => eval_ast(&rst, env)
+        rst => eval_ast(rst, env)
     }
 }
 
 // print
-fn print(ast: &MalVal) -> String {
-    ast.pr_str()
+fn print(ast: &MalVal) -> Result<String, MalErr> {
+    ast.try_pr_str(true)
 }
 
-fn rep(str: &str, env: &mut Env) -> Result<String, MalErr> {
-    let ast = read(str)?;    
-    let exp = eval(&ast, env)?;
-    Ok(print(&exp))
-}
-
-fn op(args: MalArgs, f: fn(i1: i64, i2: i64) -> i64) -> MalRet {
-    if args.len() == 0 || args.len() < 2 {
+fn op(args: MalArgs, f: fn(&MalVal, &MalVal) -> MalRet) -> MalRet {
+    if args.len() < 2 {
         return Err(ErrString(format!("Insufficient arguments: {} ", args.len())));
     }
-    // println!("eval {:?}", args);
-    let res = args
-                .iter()
-                .map(|mv| match &mv {
-                    Int(i) => *i,
-                    _      => panic!("Eval op {:?}; cannot eval datatype", args)
-                })
-                .reduce(f)
-                .map(|i| Int(i))
-                .ok_or(ErrString(format!("Could not apply op: {:?} ", args)));
-    return res;
+    let mut acc = args[0].clone();
+    for mv in args[1..].iter() {
+        acc = f(&acc, mv)?;
+    }
+    Ok(acc)
 }
 
 
+// the env the REPL starts with
+fn repl_env() -> Env {
+    let env = make_env(None);
+    let _ = env.set("+".to_string(), func(|args: MalArgs| op(args, number::add)));
+    let _ = env.set("-".to_string(), func(|args: MalArgs| op(args, number::sub)));
+    let _ = env.set("*".to_string(), func(|args: MalArgs| op(args, number::mul)));
+    let _ = env.set("/".to_string(), func(|args: MalArgs| op(args, number::div)));
+    env
+}
+
+const STEP: repl::Step = repl::Step { env: repl_env, read, eval, print, special_forms: SPECIAL_FORMS };
+
 fn main() {
-    // `()` can be used when no completer is required
-    let mut rl = Editor::<()>::new();
-    if rl.load_history(".mal-history").is_err() {
-        eprintln!("No previous history.");
-    }
+    repl::with_stack(start);
+}
 
-    // prepare Env
-    let mut env = make_env(None);
-    env.set("+".to_string(), Func(|args: MalArgs| op(args, |a,b| a+b)));
-    env.set("-".to_string(), Func(|args: MalArgs| op(args, |a,b| a-b)));
-    env.set("*".to_string(), Func(|args: MalArgs| op(args, |a,b| a*b)));
-    env.set("/".to_string(), Func(|args: MalArgs| op(args, |a,b| a/b)));
-    
-    loop {
-
-        // read
-        let readline = rl.readline("user> ");
-        match readline {
-            Ok(line) => {
-                rl.add_history_entry(&line);
-                rl.save_history(".mal-history").unwrap();
-
-                if line.len() > 0 {
-                    let res = rep(&line, &mut env);
-                    match res {
-                        Ok(out) => println!("{}", out),
-                        Err(e) => println!("Error: {}", format_error(e)),
-                    }
-                }
-            }
-            Err(ReadlineError::Interrupted) => continue,
-            Err(ReadlineError::Eof) => break,
-            Err(err) => {
-                println!("Error: {:?}", err);
-                break;
-            }
-        }
-    }
+fn start() {
+    repl::run(repl_env(), &STEP);
 }
@@ -0,0 +1,326 @@
+extern crate rustyline;
+extern crate fnv;
+extern crate num_bigint;
+extern crate num_traits;
+extern crate num_rational;
+
+use fnv::FnvHashMap;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+#[macro_use]
+#[allow(dead_code)]
+#[allow(unused_imports)]
+mod types;
+use crate::types::MalErr::ErrString;
+use crate::types::{MalVal, MalArgs, MalRet, MalErr, MapKey, Nesting};
+use crate::types::MalVal::{Func, MalFunc, Sym, Str, List, Vector, Hash, Nil, Bool};
+use crate::types::{error, format_error, func};
+
+#[allow(dead_code)]
+#[allow(unused_imports)]
+mod reader;
+
+#[allow(unused_variables)]
+#[allow(unused_imports)]
+mod printer;
+
+#[allow(dead_code)]
+#[allow(unused_imports)]
+mod env;
+use crate::env::{Env, make_env, env_bind};
+
+mod number;
+mod core;
+
+mod repl;
+
+// special forms, which eval handles itself
+const SPECIAL_FORMS: &[&str] = &[
+    "def!", "let*", "do", "if", "fn*",
+    "quote", "quasiquote", "quasiquoteexpand", "unquote", "splice-unquote"
+];
+
+// read
+fn read(str: &str) -> Result<Vec<MalVal>, MalErr> {
+    reader::read_all(str.to_string())
+}
+
+// eval
+fn eval_ast(ast: &MalVal, env: &Env) -> MalRet {
+    match ast {
+        Sym(s) => env.get(s.to_string()),
+        // eval list args
+        List(args, _) => {
+            let mut v: MalArgs = vec![];
+            for mv in args.iter() {
+                // a symbol that isn't bound is reported where its list was read
+                v.push(eval(mv, env).map_err(|e| match mv {
+                    Sym(_) => ast.locate(e),
+                    _ => e
+                })?)
+            }
+            Ok(list!(v))
+        },
+        // eval vectors
+        Vector(args, _) => {
+            let mut v: MalArgs = vec![];
+            for mv in args.iter() {
+                v.push(eval(mv, env)?)
+            }
+            Ok(vector!(v))
+        },
+        // eval hash keys and vals
+        Hash(kvs, _ ) => {
+            let mut hm: FnvHashMap<MapKey, MalVal> = FnvHashMap::default();
+            for (k, v) in kvs.iter() {
+                hm.insert(k.clone(), eval(v, env)?);
+            }
+            Ok(Hash(Rc::new(hm), Rc::new(Nil)))
+        }
+        _ => Ok(ast.clone()),
+    }
+}
+
+// quasiquote
+// expands `ast` into the list building forms that construct it:
+// unquoted parts are left to be evaluated, everything else is quoted
+
+fn is_form(ast: &MalVal, name: &str) -> Option<MalVal> {
+    match ast {
+        List(v, _) if v.len() == 2 => match &v[0] {
+            Sym(s) if s == name => Some(v[1].clone()),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+fn qq_iter(elts: &[MalVal]) -> MalRet {
+    let mut acc = list![];
+    for elt in elts.iter().rev() {
+        acc = match is_form(elt, "splice-unquote") {
+            Some(spliced) => list![Sym("concat".to_string()), spliced, acc],
+            None => list![Sym("cons".to_string()), quasiquote(elt)?, acc]
+        };
+    }
+    Ok(acc)
+}
+
+// nests once per level of ast, which needn't come from the reader
+fn quasiquote(ast: &MalVal) -> MalRet {
+    let _nesting = Nesting::enter("quasiquote")?;
+    Ok(match ast {
+        List(v, _) => match is_form(ast, "unquote") {
+            Some(unquoted) => unquoted,
+            None => qq_iter(v)?
+        },
+        Vector(v, _) => list![Sym("vec".to_string()), qq_iter(v)?],
+        Hash(..) | Sym(_) => list![Sym("quote".to_string()), ast.clone()],
+        _ => ast.clone()
+    })
+}
+
+// toplevel eval
+// forms in tail position (let* and fn* bodies, the last expr of do, the
+// branches of if) loop instead of recursing, so they don't grow the stack
+fn eval(orig_ast: &MalVal, orig_env: &Env) -> MalRet {
+    // tail calls loop here, the rest nest, so deep recursion in mal is
+    // an error, not a crash
+    let _nesting = Nesting::enter("eval")?;
+    let mut ast = orig_ast.clone();
+    let mut env = orig_env.clone();
+
+    loop {
+        let v = match ast {
+            List(ref v, _) if !v.is_empty() => v.clone(),
+            // empty lists evaluate to themselves
+            List(..) => return Ok(ast),
+            // if toplevel is not a list, evaluate its AST
+            _ => return eval_ast(&ast, &env)
+        };
+
+        match &v[0] {
+            // (def! binding val)
+            Sym(s) if s == "def!" => {
+                let binding = v.get(1).ok_or_else(|| ErrString(format!("No binding for expression: {}", ast.pr_str(true))))?;
+                if !matches!(binding, Sym(_)) {
+                    return error("def!: binding is not a symbol");
+                }
+                let val = v.get(2).ok_or_else(|| ErrString(format!("No value for expression: {}", ast.pr_str(true))))?;
+                let bindval = eval(val, &env)?;
+                env.set(binding.to_string(), bindval.clone())?;
+                return Ok(bindval);
+            },
+            // (let* [binding1 val1
+            //        bindingN valN]
+            //    body)
+            Sym(s) if s == "let*" => {
+                let bindings = v.get(1).ok_or_else(|| ErrString(format!("No bindings for expression: {}", ast.pr_str(true))))?;
+                let body = v.get(2).cloned().unwrap_or(Nil); // let can have empty bindings
+                let let_env = make_env(Some(env.clone()));
+
+                match bindings {
+                    List(bindvec, _) | Vector(bindvec, _) => {
+                        if bindvec.len() % 2 != 0 {
+                            return error("let* bindings: mismatch")
+                        }
+
+                        // create bindings
+                        for pair in bindvec.chunks(2) {
+                            match pair {
+                                [Sym(binding), expr] => {
+                                    let res = eval(expr, &let_env)?;
+                                    let_env.set(binding.to_string(), res)?;
+                                },
+                                _ => return error("let* bindings: binding is not a symbol")
+                            }
+                        }
+
+                        // TCO: body is evaluated by the next iteration with inner env
+                        ast = body;
+                        env = let_env;
+                    },
+                    _  => return error("let* bindings: not a list")
+                }
+            },
+            // (do expr1 ... exprN)
+            // evaluates each expr in order, the last one in tail position
+            Sym(s) if s == "do" => {
+                if v.len() == 1 {
+                    return Ok(Nil);
+                }
+                for expr in v[1..v.len() - 1].iter() {
+                    eval(expr, &env)?;
+                }
+                ast = v[v.len() - 1].clone();
+            },
+            // (if cond then else?)
+            // only nil and false are falsy
+            Sym(s) if s == "if" => {
+                let cond = v.get(1).ok_or_else(|| ErrString(format!("No condition for expression: {}", ast.pr_str(true))))?;
+                let branch = match eval(cond, &env)? {
+                    Nil | Bool(false) => v.get(3),
+                    _ => v.get(2)
+                };
+                match branch {
+                    Some(b) => ast = b.clone(),
+                    None => return Ok(Nil)
+                }
+            },
+            // (quote form)
+            Sym(s) if s == "quote" => {
+                return Ok(v.get(1).unwrap_or(&Nil).clone());
+            },
+            // (quasiquoteexpand form)
+            Sym(s) if s == "quasiquoteexpand" => {
+                return quasiquote(v.get(1).unwrap_or(&Nil));
+            },
+            // (quasiquote form)
+            // TCO: the expansion is evaluated by the next iteration
+            Sym(s) if s == "quasiquote" => {
+                ast = quasiquote(v.get(1).unwrap_or(&Nil))?;
+            },
+            // (fn* [param1 paramN] body)
+            // closes over the current env
+            Sym(s) if s == "fn*" => {
+                let params = v.get(1).ok_or_else(|| ErrString(format!("No params for expression: {}", ast.pr_str(true))))?;
+                let body = v.get(2).cloned().unwrap_or(Nil);
+                return Ok(MalFunc {
+                    eval,
+                    params: Rc::new(params.clone()),
+                    ast: Rc::new(body),
+                    env: env.clone(),
+                    is_macro: false,
+                    meta: Rc::new(Nil),
+                });
+            },
+
+            // regular function call
+            // (+ 1 1)
+            _ => match &eval_ast(&ast, &env)? {
+                List(v, _) => {
+                    let (fcall, fargs) = v.split_at(1);
+                    match fcall {
+                        [Func(f, _)]  => return (*f)(fargs.to_vec()),
+                        // TCO: closure body is evaluated by the next iteration
+                        [MalFunc{params, ast: fast, env: fenv, ..}] => {
+                            env = env_bind(Some(fenv.clone()), params, fargs.to_vec())?;
+                            ast = (**fast).clone();
+                        },
+                        _          => return error(&format!("'{}' is not a function", fcall[0].pr_str(true))),
+                    }
+                },
+                _ => return error("Expected a list")
+            }
+        }
+    }
+}
+
+thread_local! {
+    // the env the REPL runs in, where eval evaluates
+    static REPL_ENV: RefCell<Option<Env>> = const { RefCell::new(None) };
+}
+
+// (eval form) evaluates form in the REPL env, whatever env it's called from
+fn eval_builtin(args: MalArgs) -> MalRet {
+    let form = args.first().ok_or_else(|| ErrString("eval: expected a form".to_string()))?;
+    match REPL_ENV.with(|e| e.borrow().clone()) {
+        Some(env) => eval(form, &env),
+        None => error("eval: no REPL env")
+    }
+}
+
+// print
+fn print(ast: &MalVal) -> Result<String, MalErr> {
+    ast.try_pr_str(true)
+}
+
+// evaluates every form in str, in order, printing one result per line
+fn rep(str: &str, env: &Env) -> Result<String, MalErr> {
+    let mut out = vec![];
+    for ast in read(str)? {
+        let exp = eval(&ast, env)?;
+        out.push(print(&exp)?);
+    }
+    Ok(out.join("\n"))
+}
+
+// the env the REPL starts with
+fn repl_env() -> Env {
+    let env = make_env(None);
+    core::install(&env);
+    REPL_ENV.with(|e| *e.borrow_mut() = Some(env.clone()));
+    let _ = env.set("eval".to_string(), func(eval_builtin));
+    let argv: MalArgs = std::env::args().skip(2).map(Str).collect();
+    let _ = env.set("*ARGV*".to_string(), list!(argv));
+    let _ = rep("(def! not (fn* (a) (if a false true)))", &env);
+    let _ = rep("(def! load-file (fn* (f) (eval (cons (symbol \"do\") (concat (read-all (slurp f) f) (list nil))))))", &env);
+    env
+}
+
+const STEP: repl::Step = repl::Step { env: repl_env, read, eval, print, special_forms: SPECIAL_FORMS };
+
+fn main() {
+    repl::with_stack(start);
+}
+
+// the REPL, or the script given on the command line
+fn start() {
+    let args: Vec<String> = std::env::args().collect();
+    let env = repl_env();
+
+    // `mal script.mal arg1 argN` runs the script instead of the REPL
+    if let Some(file) = args.get(1) {
+        let _ = env.set("*FILE*".to_string(), Str(file.to_string()));
+        match rep("(load-file *FILE*)", &env) {
+            Ok(_) => std::process::exit(0),
+            Err(e) => {
+                println!("Error: {}", format_error(e));
+                std::process::exit(1);
+            }
+        }
+    }
+
+    repl::run(env, &STEP);
+}
@@ -1,11 +1,16 @@
 use std::rc::Rc;
+use std::cell::{Cell, RefCell};
 use fnv::FnvHashMap;
+use num_bigint::BigInt;
+use num_rational::BigRational;
 use std::fmt;
+use num_traits::ToPrimitive;
 //use std::collections::HashMap;
 
 use crate::types::MalErr::{ErrMalVal, ErrString};
-use crate::types::MalVal::{List, Vector, Hash, Nil, Str, Sym, Bool, Int, Atom, Keyword, Func};
+use crate::types::MalVal::{List, Vector, Hash, Nil, Str, Sym, Bool, Int, Big, Ratio, Float, Atom, Keyword, Func, MalFunc, Pos};
 use crate::types::MapKey::{Ks, Kw};
+use crate::env::{Env, env_bind};
 
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -31,6 +36,14 @@ impl MapKey {
       Kw(s) => Keyword(s.to_string())
     }
   }
+
+  pub fn from_mal_val(mv: &MalVal) -> Result<MapKey, MalErr> {
+    match mv {
+      Str(s)     => Ok(Ks(s.to_string())),
+      Keyword(s) => Ok(Kw(s.to_string())),
+      _          => Err(ErrString(format!("hashmap: key is not a string nor a keyword: '{}'", mv)))
+    }
+  }
 }
 
 #[derive(Debug, Clone)]
@@ -39,13 +52,31 @@ pub enum MalVal {
     Str(String),
     Bool(bool),
     Int(i64),
+    // ints that don't fit in an i64
+    Big(Rc<BigInt>),
+    // always normalized, with a denominator other than 1
+    Ratio(Rc<BigRational>),
+    Float(f64),
     Sym(String),
     Keyword(String),
     List(Rc<Vec<MalVal>>, Rc<MalVal>),
     Vector(Rc<Vec<MalVal>>, Rc<MalVal>),
     Hash(Rc<FnvHashMap<MapKey, MalVal>>, Rc<MalVal>),
-    Func(fn(MalArgs) -> MalRet),
-    Atom(),
+    Func(fn(MalArgs) -> MalRet, Rc<MalVal>),
+    // user defined closure: (fn* params body) plus the env it was defined in
+    // and the eval of the step that created it, so builtins can call it
+    MalFunc {
+        eval: fn(&MalVal, &Env) -> MalRet,
+        params: Rc<MalVal>,
+        ast: Rc<MalVal>,
+        env: Env,
+        is_macro: bool,
+        meta: Rc<MalVal>,
+    },
+    Atom(Rc<RefCell<MalVal>>),
+    // where a list was read from a file: the file, shared by every list read
+    // from it, and the line and column; kept in the list's metadata slot
+    Pos(Rc<str>, u32, u32),
 }
 
 impl fmt::Display for MalVal {
@@ -56,18 +87,169 @@ impl fmt::Display for MalVal {
       // operation succeeded or failed. Note that `write!` uses syntax which
       // is very similar to `println!`.
       match self {
-        Nil => write!(f, "{}", "Nil"),
+        Nil => write!(f, "Nil"),
         Str(s) => write!(f, "{}", s),
         Sym(s) => write!(f, "{}", s),
         Keyword(s) => write!(f, "{}", s),
         Bool(b) => write!(f, "{}", b),
         Int(i) => write!(f, "{}", i),
-        List(_mvs, _) => write!(f, "{}", "List"),
-        Vector(_mvs, _) => write!(f, "{}", "Vector"),
-        Hash(_mvs, _) => write!(f, "{}", "Hash"),
-        Func(_fn) => write!(f, "{}", "Func"),
-        Atom() => write!(f, "{}", "Atom")
+        Big(i) => write!(f, "{}", i),
+        Ratio(r) => write!(f, "{}", r),
+        Float(x) => write!(f, "{}", x),
+        List(_mvs, _) => write!(f, "List"),
+        Vector(_mvs, _) => write!(f, "Vector"),
+        Hash(_mvs, _) => write!(f, "Hash"),
+        Func(_fn, _) => write!(f, "Func"),
+        MalFunc{..} => write!(f, "MalFunc"),
+        Atom(_a) => write!(f, "Atom"),
+        Pos(file, line, col) => write!(f, "{}:{}:{}", file, line, col)
+      }
+  }
+}
+
+// structural equality: lists and vectors with equal elements are equal
+// builtins compare by pointer, closures never compare equal
+impl PartialEq for MalVal {
+  // the items of collections are compared from a list of pairs rather
+  // than by recursion, as values can be nested arbitrarily deep
+  fn eq(&self, other: &MalVal) -> bool {
+    let mut pending = vec![(self, other)];
+    while let Some(pair) = pending.pop() {
+      let same = match pair {
+        (Nil, Nil) => true,
+        (Bool(a), Bool(b)) => a == b,
+        (Int(a), Int(b)) => a == b,
+        (Big(a), Big(b)) => a == b,
+        (Ratio(a), Ratio(b)) => a == b,
+        (Float(a), Float(b)) => a == b,
+        (Str(a), Str(b)) => a == b,
+        (Sym(a), Sym(b)) => a == b,
+        (Keyword(a), Keyword(b)) => a == b,
+        (List(a, _), List(b, _))
+        | (Vector(a, _), Vector(b, _))
+        | (List(a, _), Vector(b, _))
+        | (Vector(a, _), List(b, _)) => {
+          pending.extend(a.iter().zip(b.iter()));
+          a.len() == b.len()
+        },
+        (Hash(a, _), Hash(b, _)) => a.len() == b.len() && a.iter().all(|(k, v)| match b.get(k) {
+          Some(w) => {
+            pending.push((v, w));
+            true
+          },
+          None => false
+        }),
+        (Func(a, _), Func(b, _)) => *a as usize == *b as usize,
+        (Atom(a), Atom(b)) => Rc::ptr_eq(a, b),
+        _ => false,
+      };
+      if !same {
+        return false;
       }
+    }
+    true
+  }
+}
+
+impl MalVal {
+  // calls a builtin or a closure with already evaluated args
+  pub fn apply(&self, args: MalArgs) -> MalRet {
+    match self {
+      Func(f, _) => f(args),
+      MalFunc{eval, params, ast, env, ..} => {
+        let fn_env = env_bind(Some(env.clone()), params, args)?;
+        eval(ast, &fn_env)
+      },
+      _ => error(&format!("'{}' is not a function", self.pr_str(true)))
+    }
+  }
+
+  // adds where this list was read to an error raised in it, if it was read from a file
+  pub fn locate(&self, e: MalErr) -> MalErr {
+    match (self, e) {
+      (List(_, meta), ErrString(msg)) => match &**meta {
+        Pos(file, line, col) => ErrString(format!("{} at {}:{}:{}", msg, file, line, col)),
+        _ => ErrString(msg)
+      },
+      (_, e) => e
+    }
+  }
+}
+
+// dropping a value nested n levels deep would recurse n times, so a
+// collection or atom that is freed hands its items over to a list,
+// which drops them one by one
+impl Drop for MalVal {
+  fn drop(&mut self) {
+    // most values free nothing nested along with them, and recursing one
+    // level is fine, so only those that would free collections are taken apart
+    if !frees_nested(self) {
+      return;
+    }
+    let mut pending = vec![];
+    take_items(self, &mut pending);
+    while let Some(mut v) = pending.pop() {
+      take_items(&mut v, &mut pending);
+    }
+  }
+}
+
+fn is_collection(v: &MalVal) -> bool {
+  matches!(v, List(..) | Vector(..) | Hash(..) | Atom(_))
+}
+
+// whether dropping v would free collections held in it
+fn frees_nested(v: &mut MalVal) -> bool {
+  match v {
+    List(items, _) | Vector(items, _) => Rc::get_mut(items).is_some_and(|items| items.iter().any(is_collection)),
+    Hash(hm, _) => Rc::get_mut(hm).is_some_and(|hm| hm.values().any(is_collection)),
+    Atom(a) => Rc::get_mut(a).is_some_and(|a| is_collection(a.get_mut())),
+    _ => false
+  }
+}
+
+// moves the items out of v, if v is the last owner of them
+fn take_items(v: &mut MalVal, pending: &mut Vec<MalVal>) {
+  match v {
+    List(items, _) | Vector(items, _) => if let Some(items) = Rc::get_mut(items) {
+      pending.append(items);
+    },
+    Hash(hm, _) => if let Some(hm) = Rc::get_mut(hm) {
+      pending.extend(hm.drain().map(|(_, v)| v));
+    },
+    Atom(a) => if let Some(a) = Rc::get_mut(a) {
+      pending.push(std::mem::replace(a.get_mut(), Nil));
+    },
+    _ => {}
+  }
+}
+
+// how far eval and the printer may nest; the REPL runs on a thread with
+// the stack for it, see repl::with_stack
+pub const MAX_NESTING: usize = 10_000;
+
+thread_local! {
+  static NESTING: Cell<usize> = const { Cell::new(0) };
+}
+
+// one level of eval or printing, given back when dropped
+pub struct Nesting;
+
+impl Nesting {
+  // what names the function that went too deep in the error
+  pub fn enter(what: &str) -> Result<Nesting, MalErr> {
+    let depth = NESTING.with(|n| n.get());
+    if depth >= MAX_NESTING {
+      return Err(ErrString(format!("{}: nested more than {} levels deep", what, MAX_NESTING)));
+    }
+    NESTING.with(|n| n.set(depth + 1));
+    Ok(Nesting)
+  }
+}
+
+impl Drop for Nesting {
+  fn drop(&mut self) {
+    NESTING.with(|n| n.set(n.get() - 1));
   }
 }
 
@@ -87,7 +269,7 @@ pub fn error(s: &str) -> MalRet {
 pub fn format_error(e: MalErr) -> String {
     match e {
         ErrString(s) => s.clone(),
-        ErrMalVal(mv) => mv.pr_str(),
+        ErrMalVal(mv) => mv.pr_str(true),
     }
 }
 
@@ -113,19 +295,35 @@ macro_rules! vector {
   }}
 }
 
+// builtin without metadata
+pub fn func(f: fn(MalArgs) -> MalRet) -> MalVal {
+  Func(f, Rc::new(Nil))
+}
+
+// an int, or a bignum when it doesn't fit in an i64
+pub fn big(n: BigInt) -> MalVal {
+  match n.to_i64() {
+    Some(i) => Int(i),
+    None => Big(Rc::new(n))
+  }
+}
+
+// a ratio, or an int when the denominator divides the numerator
+pub fn ratio(r: BigRational) -> MalVal {
+  if r.is_integer() {
+    big(r.to_integer())
+  } else {
+    Ratio(Rc::new(r))
+  }
+}
+
 pub fn hash_map(kvs: MalArgs) -> MalRet {
+  if kvs.len() % 2 != 0 {
+    return error("hashmap: odd number of keys and values");
+  }
   let mut hm: FnvHashMap<MapKey, MalVal> = FnvHashMap::default();
   for pair in kvs.chunks(2) {
-    let mvk = &pair[0];
-    let mvv = &pair[1];
-    match mvk {
-      Str(s)     => { hm.insert(Ks(s.to_string()), mvv.clone()); },
-      Keyword(s) => { hm.insert(Kw(s.to_string()), mvv.clone()); },
-      _          => {
-        let s = format!("hashmap: key is not a string nor a keyword: '{}'", mvk); 
-        return error(&s); 
-      }
-    }
+    hm.insert(MapKey::from_mal_val(&pair[0])?, pair[1].clone());
   }
 
   Ok(Hash(Rc::new(hm), Rc::new(Nil)))